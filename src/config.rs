@@ -11,24 +11,56 @@
  *
  * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
  */
-use std::{collections::HashMap, fs};
+use std::{collections::{BTreeMap, HashMap}, fs};
 use anyhow::Context;
 use log::error;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+pub mod changes;
+pub mod countries;
+pub mod drift;
+pub mod encoders;
+pub mod process;
+pub mod rc;
+pub mod templates;
+mod validation;
+pub use validation::{check_labels, Validation, TIST_OFFSET_MAX_S};
+
 type Protection = u8;
 
+/// Default ports used by the management server and the remote control of the generated ODR-DabMux config
+pub const DEFAULT_MANAGEMENT_PORT : u16 = 12720;
+pub const DEFAULT_RC_TELNET_PORT : u16 = 12721;
+pub const DEFAULT_RC_ZMQ_PORT : u16 = 12722;
+
+fn default_management_port() -> u16 { DEFAULT_MANAGEMENT_PORT }
+fn default_rc_telnet_port() -> u16 { DEFAULT_RC_TELNET_PORT }
+fn default_rc_zmq_port() -> u16 { DEFAULT_RC_ZMQ_PORT }
+fn default_mux_timeout_ms() -> u64 { 2000 }
+fn default_mux_retries() -> u32 { 1 }
+fn default_poll_interval_ms() -> u64 { 2000 }
+
+fn default_true() -> bool { true }
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Service {
     pub unique_id: String,
+    /// Disabled services are kept in the GUI config but left out of the ODR-DabMux config.
+    /// Their subchannel id and input port stay reserved.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
     pub sid: u32,
-    pub ecc: u8,
+    /// ECC of the service, or None to inherit the ensemble ECC
+    #[serde(default)]
+    pub ecc: Option<u8>,
     pub label: String,
     pub shortlabel: String,
     pub input_port: u16,
     pub bitrate: u32,
     pub protection: Protection,
+    #[serde(default)]
+    pub encoder: encoders::ServiceEncoder,
 }
 
 impl Service {
@@ -36,20 +68,51 @@ impl Service {
         format!("{:04X}", self.sid)
     }
 
+    /// ECC in hex, or empty if the service inherits the ensemble ECC
     pub fn ecc_hex(&self) -> String {
-        format!("{:02X}", self.ecc)
+        match self.ecc {
+            Some(ecc) => format!("{:02X}", ecc),
+            None => String::new(),
+        }
+    }
+
+    pub fn effective_ecc(&self, ensemble_ecc: u8) -> u8 {
+        self.ecc.unwrap_or(ensemble_ecc)
+    }
+
+    pub fn country_id(&self) -> u8 {
+        ((self.sid >> 12) & 0xF) as u8
+    }
+
+    /// Name of the service in the generated ODR-DabMux configuration, which is also its RC module
+    pub fn rc_module(&self) -> String {
+        format!("srv-{}", self.unique_id)
     }
 
-    pub fn dump_to_service_json(&self) -> serde_json::Value {
+    /// Name of the subchannel, which is also the ident of the input in the stats
+    pub fn subchannel_name(&self) -> String {
+        format!("sub-{}", self.unique_id)
+    }
+
+    pub fn component_name(&self) -> String {
+        format!("comp-{}", self.unique_id)
+    }
+
+    /// Whether a stats ident or RC module belongs to this service
+    pub fn has_ident(&self, ident: &str) -> bool {
+        ident == self.rc_module() || ident == self.subchannel_name() || ident == self.component_name()
+    }
+
+    pub fn dump_to_service_json(&self, ensemble_ecc: u8) -> serde_json::Value {
         json!({
             "id": self.sid,
-            "ecc": self.ecc,
+            "ecc": self.effective_ecc(ensemble_ecc),
             "label": self.label,
             "shortlabel": self.shortlabel,
         })
     }
 
-    pub fn dump_to_subchannel_json(&self, id: u32) -> serde_json::Value {
+    pub fn dump_to_subchannel_json(&self, id: u32, listen_address: &str) -> serde_json::Value {
         json!({
             "type": "dabplus",
             "bitrate": self.bitrate,
@@ -57,7 +120,7 @@ impl Service {
             "protection": self.protection,
 
             "inputproto": "edi",
-            "inputuri": format!("tcp://127.0.0.1:{}", self.input_port),
+            "inputuri": format!("tcp://{}:{}", listen_address, self.input_port),
             "buffer-management": "prebuffering",
             "buffer": 40,
             "prebuffering": 20
@@ -65,6 +128,25 @@ impl Service {
     }
 }
 
+/// Which remote control interface of the mux the GUI uses
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RcTransport {
+    #[default]
+    Zmq,
+    /// For muxes built without ZMQ remote control
+    Telnet,
+}
+
+impl RcTransport {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RcTransport::Zmq => "zmq",
+            RcTransport::Telnet => "telnet",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub instance_name: String,
@@ -78,6 +160,27 @@ pub struct Config {
     pub ensemble_shortlabel: String,
     pub output_edi_port: u16,
     pub output_zmq_port: u16,
+    #[serde(default = "default_management_port")]
+    pub management_port: u16,
+    #[serde(default = "default_rc_telnet_port")]
+    pub rc_telnet_port: u16,
+    #[serde(default = "default_rc_zmq_port")]
+    pub rc_zmq_port: u16,
+    #[serde(default)]
+    pub rc_transport: RcTransport,
+    /// How long to wait for an answer of the management server or the remote control
+    #[serde(default = "default_mux_timeout_ms")]
+    pub mux_timeout_ms: u64,
+    /// How many times to retry a request the mux did not answer
+    #[serde(default = "default_mux_retries")]
+    pub mux_retries: u32,
+    /// Interval at which stats and RC parameters are read in the background
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    #[serde(default)]
+    pub encoders: encoders::EncoderConfig,
+    #[serde(default)]
+    pub process: process::ProcessConfig,
     pub services: Vec<Service>,
 }
 
@@ -89,6 +192,109 @@ impl Config {
     pub fn ensemble_ecc_hex(&self) -> String {
         format!("{:02X}", self.ensemble_ecc)
     }
+
+    pub fn country_id(&self) -> u8 {
+        (self.ensemble_id >> 12) as u8
+    }
+
+    /// The country given by the ensemble ECC and EId
+    pub fn country(&self) -> Option<&'static countries::Country> {
+        countries::lookup(self.ensemble_ecc, self.country_id())
+    }
+
+    pub fn is_country(&self, country: &countries::Country) -> bool {
+        self.ensemble_ecc == country.ecc && country.has_id(self.country_id())
+    }
+
+    pub fn enabled_services(&self) -> impl Iterator<Item = &Service> {
+        self.services.iter().filter(|s| s.enabled)
+    }
+
+    pub fn service(&self, unique_id: &str) -> Option<&Service> {
+        self.services.iter().find(|s| s.unique_id == unique_id)
+    }
+
+    /// The service a stats ident or RC module belongs to, e.g. sub-foo or srv-foo
+    pub fn service_by_ident(&self, ident: &str) -> Option<&Service> {
+        self.services.iter().find(|s| s.has_ident(ident))
+    }
+
+    pub fn rc_endpoint(&self) -> crate::dabmux::RcEndpoint {
+        match self.rc_transport {
+            RcTransport::Zmq => crate::dabmux::RcEndpoint::Zmq(format!("tcp://127.0.0.1:{}", self.rc_zmq_port)),
+            RcTransport::Telnet => crate::dabmux::RcEndpoint::Telnet(format!("127.0.0.1:{}", self.rc_telnet_port)),
+        }
+    }
+
+    pub fn stats_endpoint(&self) -> String {
+        format!("tcp://127.0.0.1:{}", self.management_port)
+    }
+
+    pub fn client_settings(&self) -> crate::dabmux::ClientSettings {
+        crate::dabmux::ClientSettings {
+            timeout: std::time::Duration::from_millis(self.mux_timeout_ms),
+            retries: self.mux_retries,
+        }
+    }
+
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_ms)
+    }
+
+    /// All TCP ports the multiplexer listens on, with a description of what uses them
+    pub fn listening_ports(&self) -> Vec<(u16, String)> {
+        let mut ports = vec![
+            (self.management_port, "the management server".to_owned()),
+            (self.rc_telnet_port, "the telnet remote control".to_owned()),
+            (self.rc_zmq_port, "the ZMQ remote control".to_owned()),
+            (self.output_edi_port, "the EDI output".to_owned()),
+            (self.output_zmq_port, "the ZMQ output".to_owned()),
+        ];
+        for s in &self.services {
+            ports.push((s.input_port, format!("service {}", s.unique_id)));
+        }
+        ports
+    }
+
+    /// A default configuration for a new instance, with ports and ODR-DabMux config location
+    /// chosen so that they do not clash with the existing instances.
+    pub fn new_instance(name: &str, others: &[&Config]) -> Self {
+        let mut conf = Config {
+            instance_name: name.to_owned(),
+            ..Default::default()
+        };
+        conf.avoid_clashes(others);
+        conf
+    }
+
+    /// Change the ports and the ODR-DabMux config location so that they do not clash with the
+    /// other instances.
+    pub fn avoid_clashes(&mut self, others: &[&Config]) {
+        if others.iter().any(|c| c.dabmux_config_location == self.dabmux_config_location) {
+            self.dabmux_config_location = format!("/etc/odr-dabmux-{}.json", self.instance_name);
+        }
+
+        let mut used : Vec<u16> = others.iter()
+            .flat_map(|c| c.listening_ports())
+            .map(|(port, _)| port)
+            .collect();
+
+        let mut free_port = |port: &mut u16| {
+            while used.contains(port) {
+                *port += 1;
+            }
+            used.push(*port);
+        };
+
+        free_port(&mut self.management_port);
+        free_port(&mut self.rc_telnet_port);
+        free_port(&mut self.rc_zmq_port);
+        free_port(&mut self.output_edi_port);
+        free_port(&mut self.output_zmq_port);
+        for s in &mut self.services {
+            free_port(&mut s.input_port);
+        }
+    }
 }
 
 impl Default for Config {
@@ -104,16 +310,27 @@ impl Default for Config {
             ensemble_shortlabel: "ODR".to_owned(),
             output_edi_port: 8951,
             output_zmq_port: 8851,
+            management_port: DEFAULT_MANAGEMENT_PORT,
+            rc_telnet_port: DEFAULT_RC_TELNET_PORT,
+            rc_zmq_port: DEFAULT_RC_ZMQ_PORT,
+            rc_transport: RcTransport::Zmq,
+            mux_timeout_ms: default_mux_timeout_ms(),
+            mux_retries: default_mux_retries(),
+            poll_interval_ms: default_poll_interval_ms(),
+            encoders: Default::default(),
+            process: Default::default(),
             services: vec![
                Service {
                    unique_id: "nothing".to_owned(),
+                   enabled: true,
                    sid: 0x4DAA,
-                   ecc: 0xE1,
+                   ecc: None,
                    label: "nothing".to_owned(),
                    shortlabel: "no".to_owned(),
                    input_port: 9001,
                    bitrate: 128,
-                   protection: 2
+                   protection: 2,
+                   encoder: Default::default(),
                }
             ],
         }
@@ -122,11 +339,37 @@ impl Default for Config {
 
 const CONFIGFILE : &str = "odr-dabmux-gui-config.toml";
 
-impl Config {
+/// The configuration of all mux instances managed by this GUI, as stored in the config file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GuiConfig {
+    pub instances: Vec<Config>,
+    #[serde(default)]
+    pub templates: templates::Templates,
+    /// Scheduled actions and staged configurations, by instance name
+    #[serde(default)]
+    pub schedules: BTreeMap<String, crate::scheduler::Schedule>,
+}
+
+impl Default for GuiConfig {
+    fn default() -> Self {
+        GuiConfig {
+            instances: vec![Default::default()],
+            templates: Default::default(),
+            schedules: Default::default(),
+        }
+    }
+}
+
+impl GuiConfig {
     pub fn load() -> anyhow::Result<Self> {
         if std::path::Path::new(CONFIGFILE).exists() {
             let file_contents = fs::read_to_string(CONFIGFILE)?;
             toml::from_str(&file_contents)
+                .or_else(|_| {
+                    // Config files written before multi-instance support contain a single instance
+                    toml::from_str::<Config>(&file_contents)
+                        .map(|conf| GuiConfig { instances: vec![conf], templates: Default::default(), schedules: Default::default() })
+                })
                 .or_else(|e| {
                     error!("Failed to read existing config file: {}", e);
                     Ok(Default::default())
@@ -141,38 +384,45 @@ impl Config {
         fs::write(CONFIGFILE, toml::to_string_pretty(&self)?)
             .context("writing config file")
     }
+}
 
-    pub fn write_dabmux_json(&self) -> anyhow::Result<()> {
+impl Config {
+    /// The ODR-DabMux configuration corresponding to this instance
+    pub fn dabmux_json(&self) -> serde_json::Value {
         let now = chrono::Utc::now().to_rfc3339();
 
         let mut services = HashMap::new();
-        for s in &self.services {
-            let uid = format!("srv-{}", s.unique_id);
-            services.insert(uid, s.dump_to_service_json());
+        for s in self.enabled_services() {
+            services.insert(s.rc_module(), s.dump_to_service_json(self.ensemble_ecc));
         }
 
         let mut subchannels = HashMap::new();
-        let mut id = 0;
-        for s in &self.services {
-            id += 1;
-            let uid = format!("sub-{}", s.unique_id);
-            subchannels.insert(uid, s.dump_to_subchannel_json(id));
+        for (s, id) in self.services.iter().zip(1..) {
+            if s.enabled {
+                subchannels.insert(s.subchannel_name(), s.dump_to_subchannel_json(id, self.encoders.mux_input_address()));
+            }
         }
 
         let mut components = HashMap::new();
-        for s in &self.services {
+        for s in self.enabled_services() {
             components.insert(
-                format!("comp-{}", s.unique_id),
+                s.component_name(),
                 json!({
-                    "service": format!("srv-{}", s.unique_id),
-                    "subchannel": format!("sub-{}", s.unique_id),
+                    "service": s.rc_module(),
+                    "subchannel": s.subchannel_name(),
                     "user-applications": {
                         "userapp": "slideshow"
                     }
                 }));
         }
 
-        let new_conf = json!({
+        // Muxes that are controlled over telnet might be built without ZMQ
+        let mut remotecontrol = json!({ "telnetport": self.rc_telnet_port });
+        if self.rc_transport == RcTransport::Zmq {
+            remotecontrol["zmqendpoint"] = json!(format!("tcp://lo:{}", self.rc_zmq_port));
+        }
+
+        json!({
             "_comment": format!("Generated at {} by odr-dabmux-gui", now),
             "general": {
                 "dabmode": 1,
@@ -180,12 +430,9 @@ impl Config {
                 "syslog": false,
                 "tist": self.tist,
                 "tist_offset": self.tist_offset,
-                "managementport": 12720
-            },
-            "remotecontrol": {
-                "telnetport": 12721,
-                "zmqendpoint": "tcp://lo:12722"
+                "managementport": self.management_port
             },
+            "remotecontrol": remotecontrol,
             "ensemble": {
                 "id": self.ensemble_id,
                 "ecc": self.ensemble_ecc,
@@ -212,9 +459,11 @@ impl Config {
                     }
                 }
             }
-        });
+        })
+    }
 
-        fs::write(&self.dabmux_config_location, serde_json::to_string_pretty(&new_conf)?)
+    pub fn write_dabmux_json(&self) -> anyhow::Result<()> {
+        fs::write(&self.dabmux_config_location, serde_json::to_string_pretty(&self.dabmux_json())?)
             .context("writing dabmux config file")
     }
 }
@@ -0,0 +1,63 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The changes between the configuration a mux runs and a new one: those the remote control
+//! applies to the running mux, and those that need a restart.
+
+use std::collections::HashMap;
+use serde::Serialize;
+
+use super::{drift::{self, Difference}, Config};
+use crate::dabmux::RcChange;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Changes {
+    /// Values the remote control sets without restarting the mux
+    pub live : Vec<RcChange>,
+    /// Differences of the ODR-DabMux configuration that only a restart applies
+    pub restart : Vec<Difference>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty() && self.restart.is_empty()
+    }
+}
+
+impl Config {
+    /// Classify what differs between `running`, the configuration of the running mux, and this one
+    pub fn changes_since(&self, running: &Config) -> Changes {
+        let running_values : HashMap<(String, String), String> = running.saved_rc_values().into_iter()
+            .map(|v| ((v.module, v.param), v.value))
+            .collect();
+
+        // What the mux would run after the live changes
+        let mut patched = running.clone();
+        let mut live = Vec::new();
+        for v in self.saved_rc_values() {
+            let Some(running_value) = running_values.get(&(v.module.clone(), v.param.clone())) else {
+                // A new service, which only a restart adds
+                continue;
+            };
+            if *running_value != v.value && patched.set_rc_value(&v.module, &v.param, &v.value).is_ok() {
+                live.push(RcChange { module: v.module, param: v.param, value: v.value });
+            }
+        }
+
+        Changes {
+            live,
+            restart: drift::differences(&self.dabmux_json(), &patched.dabmux_json()),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Extended Country Codes and country identifiers, from ETSI TS 101 756 Table 7.
+//!
+//! The country id is the first nibble of the EId and of every SId. Together with
+//! the ECC, it identifies the country. Some countries have more than one country id.
+
+#[derive(Debug)]
+pub struct Country {
+    pub name : &'static str,
+    pub ecc : u8,
+    pub ids : &'static [u8],
+}
+
+impl Country {
+    /// The country id to use by default when this country is picked
+    pub fn id(&self) -> u8 {
+        self.ids[0]
+    }
+
+    pub fn ecc_hex(&self) -> String {
+        format!("{:02X}", self.ecc)
+    }
+
+    pub fn id_hex(&self) -> String {
+        format!("{:X}", self.id())
+    }
+
+    pub fn ids_hex(&self) -> String {
+        self.ids.iter().map(|id| format!("{:X}", id)).collect::<Vec<_>>().join(",")
+    }
+
+    pub fn has_id(&self, id: u8) -> bool {
+        self.ids.contains(&id)
+    }
+}
+
+macro_rules! country {
+    ($name:expr, $ecc:expr, [$($id:expr),+]) => {
+        Country { name: $name, ecc: $ecc, ids: &[$($id),+] }
+    };
+}
+
+pub static COUNTRIES : &[Country] = &[
+    country!("Albania", 0xE0, [0x9]),
+    country!("Algeria", 0xE0, [0x2]),
+    country!("Andorra", 0xE0, [0x3]),
+    country!("Armenia", 0xE4, [0xA]),
+    country!("Austria", 0xE0, [0xA]),
+    country!("Azerbaijan", 0xE3, [0xB]),
+    country!("Belarus", 0xE3, [0xF]),
+    country!("Belgium", 0xE0, [0x6]),
+    country!("Bosnia and Herzegovina", 0xE4, [0xF]),
+    country!("Bulgaria", 0xE1, [0x8]),
+    country!("Croatia", 0xE3, [0xC]),
+    country!("Cyprus", 0xE1, [0x2]),
+    country!("Czechia", 0xE2, [0x2]),
+    country!("Denmark", 0xE1, [0x9]),
+    country!("Egypt", 0xE0, [0xF]),
+    country!("Estonia", 0xE4, [0x2]),
+    country!("Finland", 0xE1, [0x6]),
+    country!("France", 0xE1, [0xF]),
+    country!("Georgia", 0xE4, [0xC]),
+    country!("Germany", 0xE0, [0xD, 0x1]),
+    country!("Gibraltar", 0xE1, [0xA]),
+    country!("Greece", 0xE1, [0x1]),
+    country!("Hungary", 0xE0, [0xB]),
+    country!("Iceland", 0xE2, [0xA]),
+    country!("Ireland", 0xE3, [0x2]),
+    country!("Israel", 0xE0, [0x4]),
+    country!("Italy", 0xE0, [0x5]),
+    country!("Jordan", 0xE1, [0x5]),
+    country!("Kazakhstan", 0xE3, [0xD]),
+    country!("Latvia", 0xE3, [0x9]),
+    country!("Lebanon", 0xE3, [0xA]),
+    country!("Libya", 0xE1, [0xD]),
+    country!("Liechtenstein", 0xE2, [0x9]),
+    country!("Lithuania", 0xE2, [0xC]),
+    country!("Luxembourg", 0xE1, [0x7]),
+    country!("Malta", 0xE0, [0xC]),
+    country!("Moldova", 0xE4, [0x1]),
+    country!("Monaco", 0xE2, [0xB]),
+    country!("Montenegro", 0xE3, [0x1]),
+    country!("Morocco", 0xE2, [0x1]),
+    country!("Netherlands", 0xE3, [0x8]),
+    country!("North Macedonia", 0xE3, [0x4]),
+    country!("Norway", 0xE2, [0xF]),
+    country!("Poland", 0xE2, [0x3]),
+    country!("Portugal", 0xE4, [0x8]),
+    country!("Romania", 0xE1, [0xE]),
+    country!("Russian Federation", 0xE0, [0x7]),
+    country!("San Marino", 0xE1, [0x3]),
+    country!("Serbia", 0xE2, [0xD]),
+    country!("Slovakia", 0xE2, [0x5]),
+    country!("Slovenia", 0xE4, [0x9]),
+    country!("Spain", 0xE2, [0xE]),
+    country!("Sweden", 0xE3, [0xE]),
+    country!("Switzerland", 0xE1, [0x4]),
+    country!("Syria", 0xE2, [0x6]),
+    country!("Tunisia", 0xE2, [0x7]),
+    country!("Turkey", 0xE3, [0x3]),
+    country!("Ukraine", 0xE4, [0x6]),
+    country!("United Kingdom", 0xE1, [0xC]),
+    country!("Vatican City State", 0xE2, [0x4]),
+];
+
+/// Find the country that uses the given ECC and country id
+pub fn lookup(ecc: u8, id: u8) -> Option<&'static Country> {
+    COUNTRIES.iter().find(|c| c.ecc == ecc && c.has_id(id))
+}
@@ -0,0 +1,120 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Comparison of the ODR-DabMux configuration generated from the saved settings with the one the
+//! mux is running, and with the file on disk.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use super::Config;
+
+/// One value that is not the same in both configurations. The path is made of the keys
+/// leading to the value, e.g. `subchannels.sub-station1.bitrate`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Difference {
+    pub path : String,
+    pub saved : Option<String>,
+    pub running : Option<String>,
+    pub description : String,
+}
+
+impl Difference {
+    fn new(path: &str, saved: Option<String>, running: Option<String>) -> Self {
+        let description = match (&saved, &running) {
+            (Some(saved), Some(running)) => format!("{} is {} in the saved configuration, but {} in the running mux", path, saved, running),
+            (Some(_), None) => format!("{} is missing in the running mux", path),
+            (None, Some(_)) => format!("{} is only in the running mux", path),
+            (None, None) => path.to_owned(),
+        };
+        Self { path: path.to_owned(), saved, running, description }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Drift {
+    pub differences : Vec<Difference>,
+    /// The running mux differs, but the file on disk is up to date, so restarting the mux applies the saved configuration
+    pub restart_needed : bool,
+    /// The file on disk differs from the saved configuration, e.g. because it was edited by hand
+    pub file_differs : bool,
+}
+
+// The mux keeps its configuration in a property tree, in which every value is a string
+fn scalar_string(v: &Value) -> String {
+    match v {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::Array(_) | Value::Object(_) => v.to_string(),
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() { key.to_owned() } else { format!("{}.{}", path, key) }
+}
+
+fn compare(path: &str, saved: Option<&Value>, running: Option<&Value>, differences: &mut Vec<Difference>) {
+    match (saved, running) {
+        (Some(Value::Object(s)), Some(Value::Object(r))) => {
+            let mut keys : Vec<&String> = s.keys().chain(r.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                // Contains the generation date
+                if path.is_empty() && key == "_comment" {
+                    continue;
+                }
+                compare(&join_path(path, key), s.get(key), r.get(key), differences);
+            }
+        },
+        (None, None) => (),
+        (s, r) => {
+            let saved = s.map(scalar_string);
+            let running = r.map(scalar_string);
+            if saved != running {
+                differences.push(Difference::new(path, saved, running));
+            }
+        },
+    }
+}
+
+/// The values that differ between two ODR-DabMux configurations. Entire services or
+/// subchannels that are only in one of them are reported once.
+pub fn differences(saved: &Value, running: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    compare("", Some(saved), Some(running), &mut differences);
+    differences
+}
+
+impl Config {
+    /// Compare the configuration the mux is running, and the configuration file if it could be read,
+    /// with the one generated from this configuration
+    pub fn drift(&self, running: &Value, file: Option<&Value>) -> Drift {
+        let generated = self.dabmux_json();
+        let differences = differences(&generated, running);
+
+        let file_differs = match file {
+            Some(file) => !self::differences(&generated, file).is_empty(),
+            None => true,
+        };
+
+        Drift {
+            restart_needed: !differences.is_empty() && !file_differs,
+            differences,
+            file_differs,
+        }
+    }
+}
@@ -0,0 +1,309 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Command lines and systemd units for the ODR-AudioEnc and ODR-PadEnc encoders that feed the services,
+//! and how the GUI runs them.
+
+use std::{fs, path::Path};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::{Config, Service};
+
+/// Number of bytes of PAD that ODR-AudioEnc inserts, enough for DLS and slideshow
+const PAD_LENGTH : u32 = 58;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct EncoderConfig {
+    /// Address the encoders send EDI to. If it is not a loopback address, the multiplexer
+    /// listens for encoders on all interfaces.
+    pub host: String,
+    pub audioenc_binary: String,
+    pub padenc_binary: String,
+    /// Directory containing, for every service, a subdirectory with slides and the DLS text file
+    pub pad_directory: String,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        EncoderConfig {
+            host: "127.0.0.1".to_owned(),
+            audioenc_binary: "/usr/local/bin/odr-audioenc".to_owned(),
+            padenc_binary: "/usr/local/bin/odr-padenc".to_owned(),
+            pad_directory: "/var/lib/odr-padenc".to_owned(),
+        }
+    }
+}
+
+impl EncoderConfig {
+    fn is_local(&self) -> bool {
+        self.host == "localhost" || self.host.starts_with("127.") || self.host == "::1"
+    }
+
+    /// Address on which the multiplexer listens for the EDI input of the encoders
+    pub fn mux_input_address(&self) -> &'static str {
+        if self.is_local() { "127.0.0.1" } else { "0.0.0.0" }
+    }
+}
+
+/// Where ODR-AudioEnc takes the audio of a service from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioSource {
+    #[default]
+    Alsa,
+    /// A stream URL, decoded with VLC
+    Stream,
+    /// A WAV file
+    File,
+}
+
+impl AudioSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AudioSource::Alsa => "alsa",
+            AudioSource::Stream => "stream",
+            AudioSource::File => "file",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AacProfile {
+    /// Chosen by ODR-AudioEnc from the bitrate
+    #[default]
+    Auto,
+    AacLc,
+    HeAac,
+    HeAacV2,
+}
+
+impl AacProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AacProfile::Auto => "auto",
+            AacProfile::AacLc => "aac_lc",
+            AacProfile::HeAac => "he_aac",
+            AacProfile::HeAacV2 => "he_aac_v2",
+        }
+    }
+
+    fn option(&self) -> Option<&'static str> {
+        match self {
+            AacProfile::Auto => None,
+            AacProfile::AacLc => Some("--aaclc"),
+            AacProfile::HeAac => Some("--sbr"),
+            AacProfile::HeAacV2 => Some("--ps"),
+        }
+    }
+}
+
+/// The encoders of one service
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ServiceEncoder {
+    /// Run the encoders from the GUI
+    pub managed: bool,
+    pub source: AudioSource,
+    /// ALSA device, stream URL or file name
+    pub input: String,
+    pub profile: AacProfile,
+    pub sample_rate: u32,
+    /// Run ODR-PadEnc for DLS and slideshow
+    pub pad: bool,
+    /// Start the encoders when the GUI starts
+    pub autostart: bool,
+    /// Start the encoders again when they exit without being asked to
+    pub auto_restart: bool,
+}
+
+impl Default for ServiceEncoder {
+    fn default() -> Self {
+        ServiceEncoder {
+            managed: false,
+            source: AudioSource::Alsa,
+            input: "default".to_owned(),
+            profile: AacProfile::Auto,
+            sample_rate: 48000,
+            pad: true,
+            autostart: false,
+            auto_restart: true,
+        }
+    }
+}
+
+/// One generated file, with a name relative to the bundle directory
+pub struct EncoderFile {
+    pub name: String,
+    pub contents: String,
+}
+
+// Quote an argument for a POSIX shell, only if needed
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_=/.:,@+".contains(c)) {
+        arg.to_owned()
+    }
+    else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+// Quote an argument for a systemd ExecStart line, where % introduces specifiers and $ environment variables
+fn systemd_quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    if !arg.is_empty() && arg.chars().all(|c| !c.is_whitespace() && c != '"' && c != '\'' && c != '\\' && c != ';') {
+        arg
+    }
+    else {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+impl Service {
+    /// Identifier of the socket between ODR-PadEnc and ODR-AudioEnc
+    fn pad_socket(&self, conf: &Config) -> String {
+        format!("{}-{}", conf.instance_name, self.unique_id)
+    }
+
+    fn pad_directory(&self, conf: &Config) -> String {
+        format!("{}/{}/{}", conf.encoders.pad_directory, conf.instance_name, self.unique_id)
+    }
+
+    pub fn audioenc_command(&self, conf: &Config) -> Vec<String> {
+        let enc = &self.encoder;
+        let mut cmd = vec![
+            conf.encoders.audioenc_binary.clone(),
+            match enc.source {
+                AudioSource::Alsa => format!("--device={}", enc.input),
+                AudioSource::Stream => format!("--vlc-uri={}", enc.input),
+                AudioSource::File => format!("--input={}", enc.input),
+            },
+            format!("--rate={}", enc.sample_rate),
+            "--channels=2".to_owned(),
+            format!("--bitrate={}", self.bitrate),
+        ];
+        cmd.extend(enc.profile.option().map(str::to_owned));
+        cmd.push(format!("--edi=tcp://{}:{}", conf.encoders.host, self.input_port));
+        if enc.pad {
+            cmd.push(format!("--pad={}", PAD_LENGTH));
+            cmd.push(format!("--pad-socket={}", self.pad_socket(conf)));
+        }
+        cmd
+    }
+
+    /// The ODR-PadEnc command, if the service has PAD
+    pub fn padenc_command(&self, conf: &Config) -> Option<Vec<String>> {
+        if !self.encoder.pad {
+            return None;
+        }
+        let dir = self.pad_directory(conf);
+        Some(vec![
+            conf.encoders.padenc_binary.clone(),
+            format!("--dir={}/slides", dir),
+            format!("--dls={}/dls.txt", dir),
+            format!("--output={}", self.pad_socket(conf)),
+        ])
+    }
+
+    /// How the GUI runs the encoders of this service
+    pub fn encoder_settings(&self, conf: &Config) -> crate::encoder::EncoderSettings {
+        let settings = |mut cmd: Vec<String>| crate::supervisor::SupervisorSettings {
+            program: cmd.remove(0),
+            args: cmd,
+            auto_restart: self.encoder.auto_restart,
+        };
+        crate::encoder::EncoderSettings {
+            audioenc: settings(self.audioenc_command(conf)),
+            padenc: self.padenc_command(conf).map(settings),
+        }
+    }
+
+    pub fn audioenc_unit_name(&self, conf: &Config) -> String {
+        format!("odr-audioenc-{}-{}.service", conf.instance_name, self.unique_id)
+    }
+
+    pub fn padenc_unit_name(&self, conf: &Config) -> String {
+        format!("odr-padenc-{}-{}.service", conf.instance_name, self.unique_id)
+    }
+}
+
+fn systemd_unit(description: &str, command: &[String]) -> String {
+    let exec_start = command.iter().map(|a| systemd_quote(a)).collect::<Vec<_>>().join(" ");
+    format!("# Generated by odr-dabmux-gui\n\
+        [Unit]\n\
+        Description={}\n\
+        After=network.target sound.target\n\
+        \n\
+        [Service]\n\
+        ExecStart={}\n\
+        Restart=always\n\
+        RestartSec=5\n\
+        \n\
+        [Install]\n\
+        WantedBy=multi-user.target\n",
+        description, exec_start)
+}
+
+impl Config {
+    /// The systemd units for the encoders of every enabled service, and a shell script with all command lines
+    pub fn encoder_files(&self) -> Vec<EncoderFile> {
+        let mut files = Vec::new();
+        let mut script = String::from("#!/bin/sh\n# Generated by odr-dabmux-gui\n");
+
+        for s in self.enabled_services() {
+            let audioenc = s.audioenc_command(self);
+            let padenc = s.padenc_command(self);
+
+            script.push_str(&format!("\n# {} ({})\n", s.label, s.unique_id));
+            for cmd in padenc.iter().chain([&audioenc]) {
+                script.push_str(&cmd.iter().map(|a| shell_quote(a)).collect::<Vec<_>>().join(" "));
+                script.push_str(" &\n");
+            }
+
+            files.push(EncoderFile {
+                name: s.audioenc_unit_name(self),
+                contents: systemd_unit(
+                    &format!("ODR-AudioEnc for {} of {}", s.label, self.instance_name),
+                    &audioenc),
+            });
+            if let Some(padenc) = padenc {
+                files.push(EncoderFile {
+                    name: s.padenc_unit_name(self),
+                    contents: systemd_unit(
+                        &format!("ODR-PadEnc for {} of {}", s.label, self.instance_name),
+                        &padenc),
+                });
+            }
+        }
+
+        script.push_str("\nwait\n");
+        files.push(EncoderFile { name: "encoders.sh".to_owned(), contents: script });
+        files
+    }
+
+    /// Write the files returned by encoder_files to the given directory
+    pub fn write_encoder_files(&self, dir: &Path) -> anyhow::Result<()> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("creating {}", dir.display()))?;
+
+        for f in self.encoder_files() {
+            let path = dir.join(&f.name);
+            fs::write(&path, f.contents)
+                .with_context(|| format!("writing {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! How the GUI runs odr-dabmux for an instance.
+
+use serde::{Deserialize, Serialize};
+
+use super::Config;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ProcessConfig {
+    pub binary: String,
+    /// Arguments given before the configuration file
+    pub args: Vec<String>,
+    /// Start odr-dabmux when the GUI starts
+    pub autostart: bool,
+    /// Start odr-dabmux again when it exits without being asked to
+    pub auto_restart: bool,
+    /// Log file written by odr-dabmux, shown in the log viewer next to its output. Empty for none.
+    pub log_file: String,
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        ProcessConfig {
+            binary: "/usr/local/bin/odr-dabmux".to_owned(),
+            args: Vec::new(),
+            autostart: false,
+            auto_restart: true,
+            log_file: String::new(),
+        }
+    }
+}
+
+impl ProcessConfig {
+    /// The arguments separated by spaces, as edited in the settings
+    pub fn args_str(&self) -> String {
+        self.args.join(" ")
+    }
+}
+
+impl Config {
+    /// How to run odr-dabmux with the generated configuration
+    pub fn supervisor_settings(&self) -> crate::supervisor::SupervisorSettings {
+        let mut args = self.process.args.clone();
+        args.push(self.dabmux_config_location.clone());
+        crate::supervisor::SupervisorSettings {
+            program: self.process.binary.clone(),
+            args,
+            auto_restart: self.process.auto_restart,
+        }
+    }
+}
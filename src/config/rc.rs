@@ -0,0 +1,81 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Mapping between the RC parameters of the running mux and the fields of the configuration,
+//! so that changes made through the RC can be saved, and live values compared to the saved ones.
+
+use serde::Serialize;
+
+use super::Config;
+
+/// Value of an RC parameter according to the saved configuration
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedRcValue {
+    pub module : String,
+    pub param : String,
+    pub value : String,
+}
+
+fn split_label_pair(value: &str) -> anyhow::Result<(String, String)> {
+    let (label, shortlabel) = value.split_once(',')
+        .ok_or_else(|| anyhow::anyhow!("Expected label,shortlabel"))?;
+    Ok((label.to_owned(), shortlabel.to_owned()))
+}
+
+impl Config {
+    /// The RC parameters that correspond to a field of this configuration, with their saved values
+    pub fn saved_rc_values(&self) -> Vec<SavedRcValue> {
+        let saved = |module: &str, param: &str, value: String| SavedRcValue {
+            module: module.to_owned(),
+            param: param.to_owned(),
+            value,
+        };
+
+        let mut values = vec![
+            saved("ensemble", "label", format!("{},{}", self.ensemble_label, self.ensemble_shortlabel)),
+            saved("mux", "tist_offset", self.tist_offset.to_string()),
+        ];
+
+        for s in self.enabled_services() {
+            values.push(saved(&s.rc_module(), "label", format!("{},{}", s.label, s.shortlabel)));
+        }
+        values
+    }
+
+    /// Whether set_rc_value can save this parameter
+    pub fn has_rc_mapping(&self, module: &str, param: &str) -> bool {
+        self.saved_rc_values().iter().any(|v| v.module == module && v.param == param)
+    }
+
+    /// Save the value of an RC parameter in the corresponding field
+    pub fn set_rc_value(&mut self, module: &str, param: &str, value: &str) -> anyhow::Result<()> {
+        match (module, param) {
+            ("ensemble", "label") => {
+                (self.ensemble_label, self.ensemble_shortlabel) = split_label_pair(value)?;
+            },
+            ("mux", "tist_offset") => {
+                self.tist_offset = value.parse()
+                    .map_err(|_| anyhow::anyhow!("TIST offset {} is not a whole number of seconds", value))?;
+            },
+            (module, "label") => {
+                let service = self.services.iter_mut()
+                    .find(|s| s.enabled && s.rc_module() == module)
+                    .ok_or_else(|| anyhow::anyhow!("No service corresponds to RC module {}", module))?;
+                (service.label, service.shortlabel) = split_label_pair(value)?;
+            },
+            _ => anyhow::bail!("{}.{} is not part of the saved configuration", module, param),
+        }
+        Ok(())
+    }
+}
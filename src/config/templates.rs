@@ -0,0 +1,200 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Named ensemble and service templates. A few templates are built in, the others are created by
+//! the user and stored in the GUI config file.
+
+use serde::{Deserialize, Serialize};
+
+use super::{Config, Protection, Service};
+
+/// The settings of a service that do not identify it, used to prefill new services
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServiceTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub bitrate: u32,
+    pub protection: Protection,
+}
+
+/// A complete ensemble, without the settings that are specific to an instance
+/// (ports, ODR-DabMux config location, EId and ECC).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EnsembleTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub tist: bool,
+    pub tist_offset: i32,
+    pub ensemble_label: String,
+    pub ensemble_shortlabel: String,
+    pub services: Vec<Service>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Templates {
+    #[serde(default)]
+    pub ensembles: Vec<EnsembleTemplate>,
+    #[serde(default)]
+    pub services: Vec<ServiceTemplate>,
+}
+
+fn service_template(name: &str, description: &str, bitrate: u32, protection: Protection) -> ServiceTemplate {
+    ServiceTemplate {
+        name: name.to_owned(),
+        description: description.to_owned(),
+        bitrate,
+        protection,
+    }
+}
+
+// Services named station1 to stationN, with numbered SIds and input ports
+fn numbered_services(count: u32, bitrate: u32, protection: Protection) -> Vec<Service> {
+    (1..=count).map(|i| Service {
+            unique_id: format!("station{}", i),
+            enabled: true,
+            sid: 0x4000 + i,
+            ecc: None,
+            label: format!("Station {}", i),
+            shortlabel: format!("Stn {}", i),
+            input_port: 9000 + i as u16,
+            bitrate,
+            protection,
+            encoder: Default::default(),
+        })
+        .collect()
+}
+
+fn ensemble_template(name: &str, description: &str, services: Vec<Service>) -> EnsembleTemplate {
+    EnsembleTemplate {
+        name: name.to_owned(),
+        description: description.to_owned(),
+        tist: true,
+        tist_offset: 0,
+        ensemble_label: "OpenDigitalRadio".to_owned(),
+        ensemble_shortlabel: "ODR".to_owned(),
+        services,
+    }
+}
+
+impl Templates {
+    pub fn builtin() -> Self {
+        Templates {
+            ensembles: vec![
+                ensemble_template("12 DAB+ stations at 72 kbps EEP-3A",
+                    "A full multiplex of twelve stations with moderate quality",
+                    numbered_services(12, 72, 3)),
+                ensemble_template("Local multiplex",
+                    "Six stations at 96 kbps EEP-3A, leaving capacity for later additions",
+                    numbered_services(6, 96, 3)),
+                ensemble_template("Single service",
+                    "One station at 128 kbps EEP-2A",
+                    numbered_services(1, 128, 2)),
+            ],
+            services: vec![
+                service_template("Music 96 kbps EEP-3A", "Typical music station", 96, 3),
+                service_template("Music 128 kbps EEP-2A", "High quality music station", 128, 2),
+                service_template("Speech 48 kbps EEP-3A", "Talk radio or news", 48, 3),
+                service_template("72 kbps EEP-3A", "Allows twelve stations in one ensemble", 72, 3),
+            ],
+        }
+    }
+
+    pub fn is_builtin_ensemble(name: &str) -> bool {
+        Self::builtin().ensembles.iter().any(|t| t.name == name)
+    }
+
+    pub fn is_builtin_service(name: &str) -> bool {
+        Self::builtin().services.iter().any(|t| t.name == name)
+    }
+
+    /// The built-in templates followed by the ones in self
+    pub fn with_builtin(&self) -> Self {
+        let mut all = Self::builtin();
+        all.ensembles.extend(self.ensembles.iter().cloned());
+        all.services.extend(self.services.iter().cloned());
+        all
+    }
+
+    pub fn ensemble(&self, name: &str) -> Option<&EnsembleTemplate> {
+        self.ensembles.iter().find(|t| t.name == name)
+    }
+
+    /// Add the templates, replacing the existing ones that have the same name.
+    /// Templates that have the name of a built-in template are ignored, and their names are returned.
+    pub fn merge(&mut self, other: Templates) -> Vec<String> {
+        let mut ignored = Vec::new();
+
+        for t in other.ensembles {
+            if Self::is_builtin_ensemble(&t.name) {
+                ignored.push(t.name);
+            }
+            else {
+                self.ensembles.retain(|e| e.name != t.name);
+                self.ensembles.push(t);
+            }
+        }
+
+        for t in other.services {
+            if Self::is_builtin_service(&t.name) {
+                ignored.push(t.name);
+            }
+            else {
+                self.services.retain(|e| e.name != t.name);
+                self.services.push(t);
+            }
+        }
+
+        ignored
+    }
+}
+
+impl EnsembleTemplate {
+    pub fn from_config(name: &str, description: &str, conf: &Config) -> Self {
+        EnsembleTemplate {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            tist: conf.tist,
+            tist_offset: conf.tist_offset,
+            ensemble_label: conf.ensemble_label.clone(),
+            ensemble_shortlabel: conf.ensemble_shortlabel.clone(),
+            services: conf.services.clone(),
+        }
+    }
+
+    /// Create the configuration of a new instance from this template. The SIds get the country id
+    /// of the default EId, and ports are chosen so that they do not clash with the other instances.
+    pub fn instantiate(&self, instance_name: &str, others: &[&Config]) -> Config {
+        let mut conf = Config {
+            instance_name: instance_name.to_owned(),
+            tist: self.tist,
+            tist_offset: self.tist_offset,
+            ensemble_label: self.ensemble_label.clone(),
+            ensemble_shortlabel: self.ensemble_shortlabel.clone(),
+            services: self.services.clone(),
+            ..Default::default()
+        };
+
+        let country_id = u32::from(conf.country_id());
+        for s in &mut conf.services {
+            if s.ecc.is_none() {
+                s.sid = (country_id << 12) | (s.sid & 0x0FFF);
+            }
+        }
+
+        conf.avoid_clashes(others);
+        conf
+    }
+}
@@ -0,0 +1,298 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::HashMap;
+use serde::Serialize;
+
+use super::{countries, Config, Service};
+
+const LABEL_MAX_LEN : usize = 16;
+const SHORTLABEL_MAX_LEN : usize = 8;
+
+/// Largest TIST offset in seconds, in either direction
+pub const TIST_OFFSET_MAX_S : i32 = 86_400;
+
+/// Number of capacity units in one DAB mode I CIF
+const CIF_CAPACITY_UNITS : u32 = 864;
+
+/// One problem found in the configuration. The field is the path of the offending value,
+/// e.g. `ensemble_label` or `services[2].sid`, and is empty when the issue is not tied to a field.
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    pub field : String,
+    pub message : String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Validation {
+    pub errors : Vec<Issue>,
+    pub warnings : Vec<Issue>,
+}
+
+impl Validation {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn error(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(Issue { field: field.into(), message: message.into() });
+    }
+
+    pub fn warning(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.warnings.push(Issue { field: field.into(), message: message.into() });
+    }
+}
+
+fn srv_field(index: usize, name: &str) -> String {
+    format!("services[{}].{}", index, name)
+}
+
+// ODR-DabMux requires every character of the short label to appear in the label, in the same order
+fn is_subsequence(short: &str, long: &str) -> bool {
+    let mut long_chars = long.chars();
+    short.chars().all(|c| long_chars.any(|l| l == c))
+}
+
+pub fn check_labels(v: &mut Validation, label_field: &str, label: &str, shortlabel_field: &str, shortlabel: &str) {
+    if label.is_empty() {
+        v.error(label_field, "Label must not be empty");
+    }
+    else if label.chars().count() > LABEL_MAX_LEN {
+        v.error(label_field, format!("Label is longer than {} characters", LABEL_MAX_LEN));
+    }
+
+    if shortlabel.is_empty() {
+        v.error(shortlabel_field, "Short label must not be empty");
+    }
+    else if shortlabel.chars().count() > SHORTLABEL_MAX_LEN {
+        v.error(shortlabel_field, format!("Short label is longer than {} characters", SHORTLABEL_MAX_LEN));
+    }
+    else if !is_subsequence(shortlabel, label) {
+        v.error(shortlabel_field, "Short label must be made of characters of the label, in the same order");
+    }
+}
+
+impl Service {
+    /// Size of the subchannel in capacity units, for EEP-A protection profiles
+    pub fn capacity_units(&self) -> Option<u32> {
+        let n = self.bitrate / 8;
+        match self.protection {
+            1 => Some(n * 12),
+            2 => Some(n * 8),
+            3 => Some(n * 6),
+            4 => Some(n * 4),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    /// Check the configuration for mistakes that would make ODR-DabMux refuse it, or that are
+    /// most probably unintended.
+    pub fn validate(&self) -> Validation {
+        let mut v = Validation::default();
+
+        if self.instance_name.is_empty() {
+            v.error("instance_name", "Instance name must not be empty");
+        }
+        else if !self.instance_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            v.error("instance_name", "Instance name may only contain letters, digits, '-' and '_'");
+        }
+
+        if self.dabmux_config_location.trim().is_empty() {
+            v.error("dabmux_config_location", "ODR-DabMux config location must not be empty");
+        }
+
+        if self.mux_timeout_ms == 0 {
+            v.error("mux_timeout_ms", "Timeout must not be zero");
+        }
+
+        if self.poll_interval_ms < 100 {
+            v.error("poll_interval_ms", "Poll interval must be at least 100 ms");
+        }
+
+        if self.encoders.host.trim().is_empty() {
+            v.error("encoders_host", "Encoder destination host must not be empty");
+        }
+
+        if self.process.binary.trim().is_empty() {
+            v.error("process_binary", "ODR-DabMux executable must not be empty");
+        }
+
+        if self.ensemble_ecc == 0 {
+            v.error("ensemble_ecc", "ECC must not be zero");
+        }
+        else if self.country().is_none() {
+            v.warning("ensemble_id",
+                format!("ECC {:02X} and country id {:X} do not correspond to any country",
+                    self.ensemble_ecc, self.country_id()));
+        }
+
+        check_labels(&mut v,
+            "ensemble_label", &self.ensemble_label,
+            "ensemble_shortlabel", &self.ensemble_shortlabel);
+
+        if !(-TIST_OFFSET_MAX_S..=TIST_OFFSET_MAX_S).contains(&self.tist_offset) {
+            v.error("tist_offset", format!("TIST offset must be between -{0} and {0} seconds", TIST_OFFSET_MAX_S));
+        }
+
+        // Every TCP port the multiplexer listens on, with the field that defines it
+        let mut ports : HashMap<u16, String> = HashMap::new();
+
+        for (field, port, name) in [
+            ("management_port", self.management_port, "the management server"),
+            ("rc_telnet_port", self.rc_telnet_port, "the telnet remote control"),
+            ("rc_zmq_port", self.rc_zmq_port, "the ZMQ remote control"),
+            ("output_edi_port", self.output_edi_port, "the EDI output"),
+            ("output_zmq_port", self.output_zmq_port, "the ZMQ output")] {
+            if port == 0 {
+                v.error(field, "Port must not be zero");
+            }
+            else if let Some(other) = ports.get(&port) {
+                v.error(field, format!("Port {} is already used by {}", port, other));
+            }
+            else {
+                ports.insert(port, name.to_owned());
+            }
+        }
+
+        if self.enabled_services().next().is_none() {
+            v.warning("", "The ensemble contains no enabled service");
+        }
+
+        let mut unique_ids : HashMap<&str, usize> = HashMap::new();
+        let mut sids : HashMap<u32, usize> = HashMap::new();
+        let mut total_cu = 0;
+
+        for (i, s) in self.services.iter().enumerate() {
+            if s.unique_id.is_empty() {
+                v.error(srv_field(i, "unique_id"), "Unique ID must not be empty");
+            }
+            else if !s.unique_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                v.error(srv_field(i, "unique_id"), "Unique ID may only contain letters, digits, '-' and '_'");
+            }
+            else if let Some(other) = unique_ids.insert(&s.unique_id, i) {
+                v.error(srv_field(i, "unique_id"), format!("Unique ID is already used by service {}", other + 1));
+            }
+
+            if s.sid == 0 || s.sid > 0xFFFF {
+                v.error(srv_field(i, "sid"), "SId of a programme service must be between 0001 and FFFF");
+            }
+            else if let Some(other) = sids.insert(s.sid, i) {
+                v.error(srv_field(i, "sid"), format!("SId is already used by service {}", other + 1));
+            }
+
+            match (s.ecc, self.country()) {
+                (Some(0), _) => v.error(srv_field(i, "ecc"), "ECC must not be zero"),
+                (Some(ecc), _) => if countries::lookup(ecc, s.country_id()).is_none() {
+                    v.warning(srv_field(i, "sid"),
+                        format!("ECC {:02X} and SId country id {:X} do not correspond to any country",
+                            ecc, s.country_id()));
+                },
+                (None, Some(country)) => if !country.has_id(s.country_id()) {
+                    v.warning(srv_field(i, "sid"),
+                        format!("SId country id {:X} does not belong to {}", s.country_id(), country.name));
+                },
+                (None, None) => if s.country_id() != self.country_id() {
+                    v.warning(srv_field(i, "sid"),
+                        format!("SId country id {:X} differs from the ensemble country id {:X}",
+                            s.country_id(), self.country_id()));
+                },
+            }
+
+            check_labels(&mut v,
+                &srv_field(i, "label"), &s.label,
+                &srv_field(i, "shortlabel"), &s.shortlabel);
+
+            if s.input_port == 0 {
+                v.error(srv_field(i, "input_port"), "Port must not be zero");
+            }
+            else if let Some(other) = ports.get(&s.input_port) {
+                v.error(srv_field(i, "input_port"), format!("Port {} is already used by {}", s.input_port, other));
+            }
+            else {
+                ports.insert(s.input_port, format!("service {}", i + 1));
+            }
+
+            if s.bitrate == 0 || s.bitrate > 192 || s.bitrate % 8 != 0 {
+                v.error(srv_field(i, "bitrate"), "DAB+ bitrate must be a multiple of 8 between 8 and 192 kbps");
+            }
+
+            match s.capacity_units() {
+                Some(cu) => if s.enabled { total_cu += cu },
+                None => v.error(srv_field(i, "protection"), "Protection must be between 1 and 4"),
+            }
+
+            if s.encoder.input.trim().is_empty() {
+                v.error(srv_field(i, "encoder_input"), "Encoder input must not be empty");
+            }
+            if s.encoder.sample_rate != 32000 && s.encoder.sample_rate != 48000 {
+                v.error(srv_field(i, "encoder_sample_rate"), "DAB+ sample rate must be 32000 or 48000 Hz");
+            }
+        }
+
+        if self.services.iter().any(|s| s.enabled && s.encoder.managed) {
+            if self.encoders.audioenc_binary.trim().is_empty() {
+                v.error("encoders_audioenc_binary", "ODR-AudioEnc executable must not be empty");
+            }
+            if self.services.iter().any(|s| s.enabled && s.encoder.managed && s.encoder.pad)
+                && self.encoders.padenc_binary.trim().is_empty() {
+                v.error("encoders_padenc_binary", "ODR-PadEnc executable must not be empty");
+            }
+        }
+
+        if total_cu > CIF_CAPACITY_UNITS {
+            v.error("", format!("The services need {} CU but the ensemble only has {} CU", total_cu, CIF_CAPACITY_UNITS));
+        }
+
+        v
+    }
+}
+
+impl Config {
+    /// Validate this configuration, and check that it can run alongside the other instances
+    /// on the same machine.
+    pub fn validate_with_others(&self, others: &[&Config]) -> Validation {
+        let mut v = self.validate();
+
+        for other in others {
+            if other.instance_name == self.instance_name {
+                v.error("instance_name", "Another instance already has this name");
+            }
+
+            if other.dabmux_config_location == self.dabmux_config_location {
+                v.error("dabmux_config_location",
+                    format!("Instance {} already writes to this file", other.instance_name));
+            }
+
+            let other_ports = other.listening_ports();
+            let mut check_port = |field: String, port: u16| {
+                if let Some((_, what)) = other_ports.iter().find(|(p, _)| *p == port) {
+                    v.error(field, format!("Port {} is already used by {} of instance {}",
+                            port, what, other.instance_name));
+                }
+            };
+
+            check_port("management_port".to_owned(), self.management_port);
+            check_port("rc_telnet_port".to_owned(), self.rc_telnet_port);
+            check_port("rc_zmq_port".to_owned(), self.rc_zmq_port);
+            check_port("output_edi_port".to_owned(), self.output_edi_port);
+            check_port("output_zmq_port".to_owned(), self.output_zmq_port);
+            for (i, s) in self.services.iter().enumerate() {
+                check_port(srv_field(i, "input_port"), s.input_port);
+            }
+        }
+
+        v
+    }
+}
@@ -11,71 +11,437 @@
  *
  * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
  */
-use anyhow::anyhow;
-use serde::Deserialize;
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, fmt, time::{Duration, Instant}};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use log::info;
+use log::{debug, info, warn};
+use tokio::sync::{mpsc, oneshot};
 
-const ZMQ_TIMEOUT : i64 = 2000;
+use crate::config::TIST_OFFSET_MAX_S;
+
+mod batch;
+mod telnet;
+
+pub use batch::{RcBatchResult, RcChange};
+
+/// Maximum number of requests waiting for the mux client thread
+const REQUEST_QUEUE_LENGTH : usize = 16;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The mux did not answer, even after retrying
+    Timeout,
+    /// The answer of the mux could not be understood
+    Protocol(String),
+    /// The mux understood the request but refused it
+    Refused(String),
+    Zmq(zmq::Error),
+    Io(std::io::Error),
+    /// Too many requests are already waiting for the mux
+    QueueFull,
+    /// The request waited too long in the queue and was dropped
+    Expired,
+    /// The mux client thread is not running
+    Stopped,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout => write!(f, "Timeout waiting for the mux"),
+            Error::Protocol(e) => write!(f, "Unexpected answer from the mux: {}", e),
+            Error::Refused(e) => write!(f, "Mux refused the request: {}", e),
+            Error::Zmq(e) => write!(f, "ZMQ error: {}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::QueueFull => write!(f, "Too many pending requests to the mux"),
+            Error::Expired => write!(f, "Request to the mux expired in the queue"),
+            Error::Stopped => write!(f, "Mux client stopped"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<zmq::Error> for Error {
+    fn from(e: zmq::Error) -> Self {
+        Error::Zmq(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Protocol(e.to_string())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        Error::Protocol(e.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// How long to wait for every answer, and how many times to retry on a new socket
+#[derive(Debug, Clone, Copy)]
+pub struct ClientSettings {
+    pub timeout : Duration,
+    pub retries : u32,
+}
+
+impl ClientSettings {
+    // Time a request may spend in the queue and being processed, assuming at most two round trips
+    fn request_timeout(&self) -> Duration {
+        self.timeout * (self.retries + 1) * 2 + self.timeout
+    }
+}
+
+/// A REQ socket that is kept open between requests, and replaced when the mux does not answer,
+/// because a REQ socket that didn't receive its answer cannot be used anymore (lazy pirate pattern).
+struct Connection {
+    endpoint : String,
+    sock : Option<zmq::Socket>,
+}
+
+impl Connection {
+    fn new(endpoint: &str) -> Self {
+        Self { endpoint: endpoint.to_owned(), sock: None }
+    }
+
+    fn socket(&mut self, ctx: &zmq::Context) -> Result<&zmq::Socket> {
+        if self.sock.is_none() {
+            let sock = ctx.socket(zmq::REQ)?;
+            // Do not keep unanswered requests around, otherwise dropping the context blocks
+            sock.set_linger(0)?;
+            sock.connect(&self.endpoint)?;
+            self.sock = Some(sock);
+        }
+        Ok(self.sock.as_ref().unwrap())
+    }
+
+    fn request(&mut self, ctx: &zmq::Context, settings: &ClientSettings, msg: &[&str]) -> Result<Vec<String>> {
+        for attempt in 0..=settings.retries {
+            let sock = self.socket(ctx)?;
+            sock.send_multipart(msg, 0)?;
+
+            let mut items = [ sock.as_poll_item(zmq::POLLIN), ];
+            zmq::poll(&mut items, settings.timeout.as_millis() as i64)?;
+            if items[0].is_readable() {
+                let mut parts = Vec::new();
+                for part in sock.recv_multipart(0)? {
+                    parts.push(String::from_utf8(part)?);
+                }
+                return Ok(parts);
+            }
+
+            warn!("No answer from {} to {} (attempt {}), reconnecting", self.endpoint, msg[0], attempt + 1);
+            self.sock = None;
+        }
+
+        Err(Error::Timeout)
+    }
+
+    fn request_message(&mut self, ctx: &zmq::Context, settings: &ClientSettings, msg: &[&str]) -> Result<String> {
+        let mut parts = self.request(ctx, settings, msg)?;
+        if parts.len() == 1 {
+            Ok(parts.remove(0))
+        }
+        else {
+            info!("multipart returned: {}", parts.join(","));
+            Err(Error::Protocol("unexpected multipart answer".to_owned()))
+        }
+    }
+}
+
+/// Descriptions of the parameters of one RC module, in the order given by the mux
+type ModuleDescriptions = Vec<(String, String)>;
+
+/// Where to reach the remote control of the mux
+#[derive(Debug, Clone)]
+pub enum RcEndpoint {
+    /// A ZMQ endpoint, e.g. `tcp://127.0.0.1:12722`
+    Zmq(String),
+    /// A TCP address, e.g. `127.0.0.1:12721`
+    Telnet(String),
+}
+
+/// A remote control interface of ODR-DabMux
+pub trait RemoteControl: Send {
+    fn endpoint(&self) -> &str;
+
+    /// The values of all parameters, in the form returned by showjson:
+    /// `{ "module1": { "param1": "value", "param2": "value" }, "module2": { ... } }`
+    fn show_all(&mut self) -> Result<Value>;
+
+    /// The modules and the descriptions of their parameters
+    fn list(&mut self) -> Result<HashMap<String, ModuleDescriptions>>;
+
+    fn get(&mut self, module: &str, param: &str) -> Result<String>;
+
+    fn set(&mut self, module: &str, param: &str, value: &str) -> Result<()>;
+}
+
+struct ZmqRemoteControl {
+    ctx : zmq::Context,
+    settings : ClientSettings,
+    conn : Connection,
+}
+
+impl RemoteControl for ZmqRemoteControl {
+    fn endpoint(&self) -> &str {
+        &self.conn.endpoint
+    }
+
+    fn show_all(&mut self) -> Result<Value> {
+        let msg = self.conn.request_message(&self.ctx, &self.settings, &["showjson"])?;
+        Ok(serde_json::from_str(&msg)?)
+    }
+
+    fn list(&mut self) -> Result<HashMap<String, ModuleDescriptions>> {
+        // Every part of the answer describes one module:
+        // { "name": "module1", "params": { "param1": "description", ... } }
+        let parts = self.conn.request(&self.ctx, &self.settings, &["list"])?;
+
+        let mut descriptions = HashMap::new();
+        for part in parts {
+            let v: Value = serde_json::from_str(&part)?;
+            let name = v.get("name").and_then(|n| n.as_str())
+                .ok_or(Error::Protocol("RC list entry without name".to_owned()))?;
+            let params = v.get("params").and_then(|p| p.as_object())
+                .ok_or_else(|| Error::Protocol(format!("RC list entry {} without params", name)))?;
+
+            let params = params.iter()
+                .map(|(param, desc)| (param.clone(), desc.as_str().unwrap_or_default().to_owned()))
+                .collect();
+            descriptions.insert(name.to_owned(), params);
+        }
+        Ok(descriptions)
+    }
+
+    fn get(&mut self, module: &str, param: &str) -> Result<String> {
+        let mut resp = self.conn.request(&self.ctx, &self.settings, &["get", module, param])?;
+
+        if resp.len() > 1 && resp[0] == "fail" {
+            Err(Error::Refused(resp.remove(1)))
+        }
+        else if resp.len() == 1 {
+            Ok(resp.remove(0))
+        }
+        else {
+            Err(Error::Protocol(format!("unknown answer to get: {}", resp.join(","))))
+        }
+    }
+
+    fn set(&mut self, module: &str, param: &str, value: &str) -> Result<()> {
+        let resp = self.conn.request(&self.ctx, &self.settings, &["set", module, param, value])?;
+
+        if !resp.is_empty() && resp[0] == "ok" {
+            Ok(())
+        }
+        else if resp.len() > 1 && resp[0] == "fail" {
+            Err(Error::Refused(resp[1].clone()))
+        }
+        else {
+            Err(Error::Protocol(format!("unknown answer to set: {}", resp.join(","))))
+        }
+    }
+}
 
 pub struct DabMux {
     ctx : zmq::Context,
-    rc_endpoint : String,
-    stats_endpoint : String,
+    settings : ClientSettings,
+    rc : Box<dyn RemoteControl>,
+    stats : Connection,
+    /// Answer to the RC list command, fetched again when the modules reported by showjson change
+    descriptions : HashMap<String, ModuleDescriptions>,
+    /// The modules reported by showjson when the descriptions were fetched, some of which
+    /// list may not describe
+    described_modules : BTreeSet<String>,
+}
+
+/// What values an RC parameter accepts, used to choose the editor on the dashboard
+/// and to check values before sending them to the mux
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParamKind {
+    /// Sent as 0 or 1
+    Bool,
+    Int { min: i64, max: i64 },
+    Enum { values: &'static [&'static str] },
+    /// Label and short label, sent together as `label,shortlabel`
+    LabelPair,
+    ReadOnly,
+    String,
 }
 
+impl ParamKind {
+    /// The kind of a parameter of ODR-DabMux. Parameters not known here are strings.
+    pub fn of(module: &str, param: &str) -> Self {
+        match (module, param) {
+            ("mux", "frames") => ParamKind::ReadOnly,
+            ("clocktai", "expiry") => ParamKind::ReadOnly,
+            ("mux", "tist_offset") => ParamKind::Int { min: -i64::from(TIST_OFFSET_MAX_S), max: i64::from(TIST_OFFSET_MAX_S) },
+            (_, "label") => ParamKind::LabelPair,
+            (_, "enable") | (_, "encryption") => ParamKind::Bool,
+            // ZMQ input buffers, in number of frames
+            (_, "buffer") | (_, "prebuffering") => ParamKind::Int { min: 0, max: 10_000 },
+            // EDI input delay, in ms
+            (_, "tistdelay") => ParamKind::Int { min: -10_000, max: 10_000 },
+            (_, "buffermanagement") => ParamKind::Enum { values: &["prebuffering", "timestamped"] },
+            (_, "pty") => ParamKind::Int { min: 0, max: 31 },
+            (_, "ptysd") => ParamKind::Enum { values: &["static", "dynamic"] },
+            _ => ParamKind::String,
+        }
+    }
+
+    /// The kind of a parameter as read from the mux, where a label without short label is a plain
+    /// string. Parameters that were not read get the kind given by `of`.
+    pub fn reported(params: &[Param], module: &str, param: &str) -> Self {
+        params.iter()
+            .find(|p| p.module == module && p.param == param)
+            .map(|p| p.kind.clone())
+            .unwrap_or_else(|| Self::of(module, param))
+    }
+
+    /// Check a value before it is sent to the mux
+    pub fn check(&self, value: &str) -> std::result::Result<(), String> {
+        match self {
+            ParamKind::Bool => match value {
+                "0" | "1" => Ok(()),
+                _ => Err(format!("{} is not 0 or 1", value)),
+            },
+            ParamKind::Int { min, max } => match value.parse::<i64>() {
+                Ok(i) if (*min..=*max).contains(&i) => Ok(()),
+                Ok(i) => Err(format!("{} is not between {} and {}", i, min, max)),
+                Err(_) => Err(format!("{} is not an integer", value)),
+            },
+            ParamKind::Enum { values } => {
+                if values.contains(&value) {
+                    Ok(())
+                }
+                else {
+                    Err(format!("{} is not one of {}", value, values.join(", ")))
+                }
+            },
+            ParamKind::LabelPair => {
+                let (label, shortlabel) = value.split_once(',')
+                    .ok_or_else(|| "Expected label,shortlabel".to_owned())?;
+                let mut v = crate::config::Validation::default();
+                crate::config::check_labels(&mut v, "label", label, "shortlabel", shortlabel);
+                if v.is_ok() {
+                    Ok(())
+                }
+                else {
+                    Err(v.errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join(", "))
+                }
+            },
+            ParamKind::ReadOnly => Err("Parameter is read-only".to_owned()),
+            ParamKind::String => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Param {
     pub module : String,
     pub param : String,
     pub value : String,
+    pub kind : ParamKind,
+    /// Help text from the RC list command
+    pub description : Option<String>,
+    /// Listed by the mux, but missing from showjson so its value is unknown
+    pub write_only : bool,
 }
 
 
 impl DabMux {
-    pub fn new() -> Self {
+    pub fn new(rc_endpoint: &RcEndpoint, stats_endpoint: &str, settings: ClientSettings) -> Self {
         let ctx = zmq::Context::new();
+        let rc : Box<dyn RemoteControl> = match rc_endpoint {
+            RcEndpoint::Zmq(endpoint) => Box::new(ZmqRemoteControl {
+                ctx: ctx.clone(),
+                settings,
+                conn: Connection::new(endpoint),
+            }),
+            RcEndpoint::Telnet(address) => Box::new(telnet::TelnetRemoteControl::new(address, settings)),
+        };
+
         Self {
             ctx,
-            rc_endpoint : "tcp://127.0.0.1:12722".to_owned(),
-            stats_endpoint : "tcp://127.0.0.1:12720".to_owned(),
+            settings,
+            rc,
+            stats : Connection::new(stats_endpoint),
+            descriptions : HashMap::new(),
+            described_modules : BTreeSet::new(),
         }
     }
 
-    fn value_to_params(v: Value) -> anyhow::Result<Vec<Param>> {
-        let root = v.as_object().ok_or(anyhow!("RC data is not a JSON object"))?;
+    fn value_to_params(v: Value) -> Result<Vec<Param>> {
+        let root = v.as_object().ok_or(Error::Protocol("RC data is not a JSON object".to_owned()))?;
 
         let mut all_params = Vec::new();
 
         for (module_name, params_value) in root {
-            let params = params_value.as_object().ok_or(anyhow!("RC module {} is not a JSON object", module_name))?;
+            let params = params_value.as_object()
+                .ok_or_else(|| Error::Protocol(format!("RC module {} is not a JSON object", module_name)))?;
 
             // ODR-DabMux doesn't allow setting only label through the RC, so we have to merge them together
-            if let (Some(Value::String(l)), Some(Value::String(sl))) = (params.get("label").clone(), params.get("shortlabel").clone()) {
+            let label_pair = (params.get("label"), params.get("shortlabel"));
+            let merged = matches!(label_pair, (Some(Value::String(_)), Some(Value::String(_))));
+            if let (Some(Value::String(l)), Some(Value::String(sl))) = label_pair {
                 let value = format!("{},{}", l, sl);
                 all_params.push(
                     Param {
                         module: module_name.to_owned(),
                         param: "label".to_owned(),
-                        value
+                        value,
+                        kind: ParamKind::LabelPair,
+                        description: None,
+                        write_only: false,
                     });
             }
 
             for (param_name, value_json) in params {
-                if !(param_name == "label" || param_name == "shortlabel") {
+                if !(merged && (param_name == "label" || param_name == "shortlabel")) {
                     let value = match value_json {
                         Value::Null => "null".to_owned(),
                         Value::Bool(b) => if *b { "1".to_owned() } else { "0".to_owned() },
                         Value::Number(n) => n.to_string(),
                         Value::String(s) => s.clone(),
-                        Value::Array(_) => return Err(anyhow!(format!("Unexpected array in {}.{}", module_name, param_name))),
-                        Value::Object(_) => return Err(anyhow!(format!("Unexpected object in {}.{}", module_name, param_name))),
+                        Value::Array(_) => return Err(Error::Protocol(format!("Unexpected array in {}.{}", module_name, param_name))),
+                        Value::Object(_) => return Err(Error::Protocol(format!("Unexpected object in {}.{}", module_name, param_name))),
+                    };
+
+                    let kind = match ParamKind::of(module_name, param_name) {
+                        // A label without short label cannot be edited as a pair
+                        ParamKind::LabelPair => ParamKind::String,
+                        kind => kind,
+                    };
+
+                    // Some modules report booleans as text
+                    let value = match (&kind, value.as_str()) {
+                        (ParamKind::Bool, "true") => "1".to_owned(),
+                        (ParamKind::Bool, "false") => "0".to_owned(),
+                        _ => value,
                     };
 
                     all_params.push(
                         Param {
                             module: module_name.to_owned(),
                             param: param_name.to_owned(),
-                            value
+                            value,
+                            kind,
+                            description: None,
+                            write_only: false,
                         });
                 }
             }
@@ -84,133 +450,355 @@ impl DabMux {
         Ok(all_params)
     }
 
-    fn poll_multipart(sock: &zmq::Socket) -> anyhow::Result<Vec<String>> {
-        let mut items = [ sock.as_poll_item(zmq::POLLIN), ];
-        zmq::poll(&mut items, ZMQ_TIMEOUT).unwrap();
-        if items[0].is_readable() {
-            let mut parts = Vec::new();
-            for part in sock.recv_multipart(0)? {
-                let p = String::from_utf8(part)?;
-                parts.push(p);
+    // Add the descriptions to the parameters, and add the listed parameters that showjson omits
+    fn merge_descriptions(&self, params: &mut Vec<Param>) {
+        for p in params.iter_mut() {
+            p.description = self.descriptions.get(&p.module)
+                .and_then(|descs| descs.iter().find(|(name, _)| *name == p.param))
+                .map(|(_, desc)| desc.clone());
+
+            if p.description.as_deref().is_some_and(|d| d.contains("[read-only]")) {
+                p.kind = ParamKind::ReadOnly;
             }
-            Ok(parts)
-        }
-        else {
-            Err(anyhow!("ZMQ timeout"))
         }
-    }
 
-    fn poll_message(sock: &zmq::Socket) -> anyhow::Result<String> {
-        let parts = Self::poll_multipart(&sock)?;
-        if parts.len() == 1 {
-            Ok(parts[0].clone())
-        }
-        else {
-            info!("multipart returned: {}", parts.join(","));
-            return Err(anyhow!("unexpected multipart answer"));
+        let mut modules : Vec<_> = self.descriptions.iter().collect();
+        modules.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (module, descs) in modules {
+            let has_label_pair = params.iter().any(|p| p.module == *module && p.kind == ParamKind::LabelPair);
+
+            for (param, desc) in descs {
+                let shown = params.iter().any(|p| p.module == *module && p.param == *param);
+                // The short label is part of the label pair
+                let merged = has_label_pair && param == "shortlabel";
+
+                if !shown && !merged {
+                    params.push(Param {
+                        module: module.clone(),
+                        param: param.clone(),
+                        value: String::new(),
+                        kind: ParamKind::of(module, param),
+                        description: Some(desc.clone()),
+                        write_only: true,
+                    });
+                }
+            }
         }
     }
 
-    pub fn get_rc_parameters(&mut self) -> anyhow::Result<Vec<Param>> {
-        let sock = self.ctx.socket(zmq::REQ)?;
-        sock.connect(&self.rc_endpoint)?;
-        sock.send("showjson", 0)?;
+    pub fn get_rc_parameters(&mut self) -> Result<Vec<Param>> {
+        let mut params = Self::value_to_params(self.rc.show_all()?)?;
 
-        let msg = Self::poll_message(&sock)?;
+        // The list of modules only changes when the mux is restarted with another configuration
+        let modules : BTreeSet<String> = params.iter().map(|p| p.module.clone()).collect();
+        if modules != self.described_modules {
+            match self.rc.list() {
+                Ok(descriptions) => {
+                    self.descriptions = descriptions;
+                    self.described_modules = modules;
+                },
+                // The values are still useful without descriptions
+                Err(e) => warn!("Cannot get RC parameter descriptions from {}: {}", self.rc.endpoint(), e),
+            }
+        }
 
-        // JSON structure:
-        // { "module1": { "param1": "value", "param2": "value" }, "module2": { ... } }
-        let v: Value = serde_json::from_str(&msg)?;
-        Self::value_to_params(v)
+        self.merge_descriptions(&mut params);
+        Ok(params)
     }
 
-    pub fn set_rc_parameter(&mut self, module: &str, param: &str, value: &str) -> anyhow::Result<()> {
-        let sock = self.ctx.socket(zmq::REQ)?;
-        sock.connect(&self.rc_endpoint)?;
-        sock.send_multipart(["set", module, param, value], 0)?;
+    pub fn get_rc_parameter(&mut self, module: &str, param: &str) -> Result<String> {
+        self.rc.get(module, param)
+    }
 
-        let resp = Self::poll_multipart(&sock)?;
+    pub fn set_rc_parameter(&mut self, module: &str, param: &str, value: &str) -> Result<()> {
+        self.rc.set(module, param, value)
+    }
 
-        //eprintln!("SET_RC: {}", j);
-        if resp.len() > 0 && resp[0] == "ok" {
-            Ok(())
+    /// The configuration the mux was started with, as returned by the management server.
+    /// All values are strings, because the mux stores its configuration in a property tree.
+    pub fn get_running_config(&mut self) -> Result<Value> {
+        let msg = self.stats.request_message(&self.ctx, &self.settings, &["getptree"])?;
+        let v : Value = serde_json::from_str(&msg)
+            .map_err(|_| Error::Protocol(format!("getptree answer is not JSON: {}", msg.trim())))?;
+        if v.is_object() {
+            Ok(v)
         }
         else {
-            if resp.len() > 1 && resp[0] == "fail" {
-                Err(anyhow!(format!("Failed to set RC: {}", resp[1])))
-            }
-            else {
-                Err(anyhow!("Failed to set RC: unknown error"))
-            }
+            Err(Error::Protocol("getptree answer is not a JSON object".to_owned()))
         }
     }
 
-    pub fn get_stats(&mut self) -> anyhow::Result<Stats> {
-        let sock = self.ctx.socket(zmq::REQ)?;
-        sock.connect(&self.stats_endpoint)?;
-        sock.send("info", 0)?;
+    pub fn get_stats(&mut self) -> Result<Stats> {
+        let info_json : Value = serde_json::from_str(
+            &self.stats.request_message(&self.ctx, &self.settings, &["info"])?)?;
 
-        let info_json : Value = serde_json::from_str(&Self::poll_message(&sock)?)?;
+        let service = info_json.get("service")
+            .and_then(|v| v.as_str())
+            .ok_or(Error::Protocol("missing service in stats response".to_owned()))?;
+
+        if !service.starts_with("ODR-DabMux") {
+            info!("stats info service is {}", service);
+            return Err(Error::Protocol(format!("wrong service {} in stats", service)));
+        }
 
-        if let Some(service) = info_json.get("service")
+        let version = info_json.get("version")
             .and_then(|v| v.as_str())
-        {
-            if !service.starts_with("ODR-DabMux") {
-                info!("stats info service is {}", service);
-                return Err(anyhow!("Wrong service in stats"));
-            }
+            .unwrap_or("UNKNOWN")
+            .to_owned();
+        let uptime = info_json.get("uptime").and_then(|v| v.as_u64());
 
-            let version = info_json.get("version")
-                .and_then(|v| v.as_str())
-                .or(Some("UNKNOWN"))
-                .unwrap()
-                .to_owned();
-
-            sock.send("values", 0)?;
-            let values_json : Value = serde_json::from_str(&Self::poll_message(&sock)?)?;
-            match values_json.get("values")
-                .and_then(|v| v.as_object()) {
-                Some(v) => {
-                    let mut input_stats : Vec<(String, InputStat)> = Vec::new();
-
-                    for (k, v) in v {
-                        let is = v.get("inputstat")
-                            .ok_or(anyhow!("inputstat missing"))?;
-                        let stat : InputStat = serde_json::from_value(is.clone())?;
-                        input_stats.push((k.clone(), stat));
-                    }
+        let values_json : Value = serde_json::from_str(
+            &self.stats.request_message(&self.ctx, &self.settings, &["values"])?)?;
+        let values = values_json.get("values")
+            .and_then(|v| v.as_object())
+            .ok_or(Error::Protocol("values isn't an object".to_owned()))?;
 
-                    input_stats.sort_by_key(|v| v.0.clone());
+        let mut input_stats : Vec<(String, InputStat)> = Vec::new();
+        let mut other_values = BTreeMap::new();
 
-                    Ok(Stats { version, input_stats })
+        for (k, v) in values {
+            // Inputs of types we do not know might report something else than inputstat
+            match v.get("inputstat").map(|is| serde_json::from_value::<InputStat>(is.clone())) {
+                Some(Ok(stat)) => input_stats.push((k.clone(), stat)),
+                Some(Err(e)) => {
+                    debug!("Cannot parse inputstat of {}: {}", k, e);
+                    other_values.insert(k.clone(), v.clone());
+                },
+                None => {
+                    other_values.insert(k.clone(), v.clone());
                 },
-                None => Err(anyhow!("values isn't an object")),
             }
         }
-        else {
-            Err(anyhow!("Missing service in stats response"))
+
+        input_stats.sort_by_key(|v| v.0.clone());
+
+        Ok(Stats { format: STATS_FORMAT, version, uptime, input_stats, other_values })
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut DabMux) + Send>;
+
+struct Request {
+    deadline : Instant,
+    job : Job,
+}
+
+/// Async access to a DabMux that runs on its own thread, so that the blocking ZMQ calls
+/// never hold up the async runtime. Requests are processed one after the other, and
+/// requests that expire while waiting in the queue are dropped.
+#[derive(Clone)]
+pub struct DabMuxHandle {
+    tx : mpsc::Sender<Request>,
+    request_timeout : Duration,
+}
+
+impl DabMuxHandle {
+    /// Start the client thread. It stops when the last handle is dropped.
+    pub fn spawn(name: &str, mut dabmux: DabMux) -> Self {
+        let (tx, mut rx) = mpsc::channel::<Request>(REQUEST_QUEUE_LENGTH);
+        let request_timeout = dabmux.settings.request_timeout();
+
+        let thread_name = format!("dabmux-{}", name);
+        std::thread::Builder::new()
+            .name(thread_name.clone())
+            .spawn(move || {
+                while let Some(req) = rx.blocking_recv() {
+                    if Instant::now() > req.deadline {
+                        debug!("{}: dropping expired request", thread_name);
+                        continue;
+                    }
+                    (req.job)(&mut dabmux);
+                }
+                debug!("{}: stopped", thread_name);
+            })
+            .expect("spawning mux client thread");
+
+        Self { tx, request_timeout }
+    }
+
+    async fn call<T, F>(&self, f: F) -> Result<T>
+        where
+            T: Send + 'static,
+            F: FnOnce(&mut DabMux) -> Result<T> + Send + 'static
+    {
+        self.call_with_timeout(self.request_timeout, f).await
+    }
+
+    async fn call_with_timeout<T, F>(&self, timeout: Duration, f: F) -> Result<T>
+        where
+            T: Send + 'static,
+            F: FnOnce(&mut DabMux) -> Result<T> + Send + 'static
+    {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let deadline = Instant::now() + timeout;
+
+        let job : Job = Box::new(move |dabmux| {
+            // The caller might have given up already
+            let _ = reply_tx.send(f(dabmux));
+        });
+
+        self.tx.try_send(Request { deadline, job })
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => Error::QueueFull,
+                mpsc::error::TrySendError::Closed(_) => Error::Stopped,
+            })?;
+
+        match tokio::time::timeout_at(deadline.into(), reply_rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(Error::Expired),
+            Err(_) => Err(Error::Timeout),
         }
     }
+
+    pub async fn get_rc_parameters(&self) -> Result<Vec<Param>> {
+        self.call(|dabmux| dabmux.get_rc_parameters()).await
+    }
+
+    pub async fn get_rc_parameter(&self, module: &str, param: &str) -> Result<String> {
+        let (module, param) = (module.to_owned(), param.to_owned());
+        self.call(move |dabmux| dabmux.get_rc_parameter(&module, &param)).await
+    }
+
+    pub async fn set_rc_parameter(&self, module: &str, param: &str, value: &str) -> Result<()> {
+        let (module, param, value) = (module.to_owned(), param.to_owned(), value.to_owned());
+        self.call(move |dabmux| dabmux.set_rc_parameter(&module, &param, &value)).await
+    }
+
+    pub async fn apply_rc_batch(&self, changes: Vec<RcChange>) -> Result<RcBatchResult> {
+        // Every change needs up to four round trips: reading the label pair, setting and rolling back,
+        // after reading all parameters
+        let timeout = self.request_timeout * 2 * (changes.len() as u32 + 1);
+        self.call_with_timeout(timeout, move |dabmux| Ok(dabmux.apply_rc_batch(&changes))).await
+    }
+
+    pub async fn get_stats(&self) -> Result<Stats> {
+        self.call(|dabmux| dabmux.get_stats()).await
+    }
+
+    pub async fn get_running_config(&self) -> Result<Value> {
+        self.call(|dabmux| dabmux.get_running_config()).await
+    }
 }
 
-#[derive(Debug)]
+/// Version of the serialised Stats, incremented when fields are renamed or removed
+pub const STATS_FORMAT : u32 = 2;
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Stats {
+    /// Always STATS_FORMAT, for the users of the snapshot API
+    pub format : u32,
     pub version : String,
+    /// Uptime of the mux in seconds, if the management server reports it
+    pub uptime : Option<u64>,
     pub input_stats : Vec<(String, InputStat)>,
+    /// Values of the management server that are not input statistics we understand
+    pub other_values : BTreeMap<String, Value>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Summary of the state of a mux, derived from the state of its inputs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Health {
+    Ok,
+    Degraded,
+    Failed,
+    Unreachable,
+}
+
+impl Health {
+    pub fn css_class(&self) -> &'static str {
+        match self {
+            Health::Ok => "health-ok",
+            Health::Degraded => "health-degraded",
+            Health::Failed => "health-failed",
+            Health::Unreachable => "health-unreachable",
+        }
+    }
+}
+
+impl std::fmt::Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Health::Ok => write!(f, "OK"),
+            Health::Degraded => write!(f, "Degraded"),
+            Health::Failed => write!(f, "Failed"),
+            Health::Unreachable => write!(f, "Unreachable"),
+        }
+    }
+}
+
+impl Stats {
+    /// All inputs streaming is healthy, unstable or silent inputs degrade the mux,
+    /// and inputs without data make it fail.
+    pub fn health(&self) -> Health {
+        let mut health = Health::Ok;
+        for (_, is) in &self.input_stats {
+            match is.state.as_deref() {
+                Some("Streaming") => (),
+                Some("NoData") => return Health::Failed,
+                _ => health = Health::Degraded,
+            }
+        }
+        health
+    }
+}
+
+/// Statistics of one input. Every field is optional because it depends on the version
+/// of the mux and on the type of input.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct InputStat {
-    pub max_fill : u32,
-    pub min_fill : u32,
-    pub num_underruns : u64,
-    pub num_overruns : u64,
-    pub peak_left : i32,
-    pub peak_right : i32,
-    pub peak_left_slow : i32,
-    pub peak_right_slow : i32,
+    /// Buffer fill, in number of frames
+    pub max_fill : Option<u32>,
+    pub min_fill : Option<u32>,
+    pub num_underruns : Option<u64>,
+    pub num_overruns : Option<u64>,
+    /// Audio levels in dBFS
+    pub peak_left : Option<i32>,
+    pub peak_right : Option<i32>,
+    pub peak_left_slow : Option<i32>,
+    pub peak_right_slow : Option<i32>,
     pub state : Option<String>,
     pub version : Option<String>,
+    /// Seconds since the encoder connected
     pub uptime : Option<u64>,
-    pub last_tist_offset : i32,
+    pub last_tist_offset : Option<i32>,
+    /// Values that are specific to some input types, e.g. EDI or ZMQ counters
+    #[serde(flatten)]
+    pub extra : BTreeMap<String, Value>,
+}
+
+fn optional<T: fmt::Display>(v: &Option<T>) -> String {
+    match v {
+        Some(v) => v.to_string(),
+        None => "N/A".to_owned(),
+    }
+}
+
+fn level(v: &Option<i32>) -> String {
+    match v {
+        Some(v) => format!("{} dBFS", v),
+        None => "N/A".to_owned(),
+    }
+}
+
+impl InputStat {
+    /// The values shown in the input table of the dashboard, in the order of its columns
+    pub fn cells(&self) -> Vec<String> {
+        vec![
+            optional(&self.max_fill),
+            optional(&self.min_fill),
+            optional(&self.num_underruns),
+            optional(&self.num_overruns),
+            level(&self.peak_left),
+            level(&self.peak_right),
+            level(&self.peak_left_slow),
+            level(&self.peak_right_slow),
+            optional(&self.state),
+            optional(&self.version),
+            optional(&self.uptime),
+            optional(&self.last_tist_offset),
+            self.extra.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(" "),
+        ]
+    }
 }
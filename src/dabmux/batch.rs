@@ -0,0 +1,140 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Several RC changes applied together: either all of them are applied, or the ones already
+//! applied are reverted to the values they had before the batch.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{DabMux, ParamKind, Result};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RcChange {
+    pub module : String,
+    pub param : String,
+    pub value : String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RcChangeStatus {
+    Applied,
+    /// This change was invalid or refused by the mux
+    Failed { error: String },
+    /// Not attempted because another change failed
+    NotApplied,
+    /// Applied, then reverted because a later change failed
+    RolledBack,
+    /// Applied, but reverting it failed, so the mux keeps the new value
+    RollbackFailed { error: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RcChangeResult {
+    pub module : String,
+    pub param : String,
+    pub value : String,
+    /// Value before the batch, if it could be read
+    pub previous : Option<String>,
+    #[serde(flatten)]
+    pub status : RcChangeStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RcBatchResult {
+    /// All changes were applied
+    pub ok : bool,
+    pub results : Vec<RcChangeResult>,
+}
+
+impl DabMux {
+    // The current value in the form accepted by set, which for labels includes the short label
+    fn read_for_rollback(&mut self, module: &str, param: &str, kind: &ParamKind) -> Result<String> {
+        let value = self.rc.get(module, param)?;
+        if *kind == ParamKind::LabelPair && !value.contains(',') {
+            let shortlabel = self.rc.get(module, "shortlabel")?;
+            Ok(format!("{},{}", value, shortlabel))
+        }
+        else {
+            Ok(value)
+        }
+    }
+
+    /// Validate all changes, read the current values, then apply the changes in order.
+    /// If one fails, the changes already applied are reverted in reverse order.
+    pub fn apply_rc_batch(&mut self, changes: &[RcChange]) -> RcBatchResult {
+        let mut results : Vec<RcChangeResult> = changes.iter()
+            .map(|c| RcChangeResult {
+                module: c.module.clone(),
+                param: c.param.clone(),
+                value: c.value.clone(),
+                previous: None,
+                status: RcChangeStatus::NotApplied,
+            })
+            .collect();
+
+        // Whether a label has a short label depends on the module
+        let params = self.get_rc_parameters().unwrap_or_default();
+        let kinds : Vec<ParamKind> = changes.iter()
+            .map(|c| ParamKind::reported(&params, &c.module, &c.param))
+            .collect();
+
+        let mut valid = true;
+        for ((c, r), kind) in changes.iter().zip(results.iter_mut()).zip(&kinds) {
+            if let Err(e) = kind.check(&c.value) {
+                r.status = RcChangeStatus::Failed { error: e };
+                valid = false;
+            }
+        }
+        if !valid {
+            return RcBatchResult { ok: false, results };
+        }
+
+        for ((c, r), kind) in changes.iter().zip(results.iter_mut()).zip(&kinds) {
+            match self.read_for_rollback(&c.module, &c.param, kind) {
+                Ok(value) => r.previous = Some(value),
+                Err(e) => {
+                    r.status = RcChangeStatus::Failed { error: format!("Cannot read the current value: {}", e) };
+                    return RcBatchResult { ok: false, results };
+                },
+            }
+        }
+
+        for i in 0..changes.len() {
+            let c = &changes[i];
+            if let Err(e) = self.rc.set(&c.module, &c.param, &c.value) {
+                results[i].status = RcChangeStatus::Failed { error: e.to_string() };
+                self.roll_back(&mut results[..i]);
+                return RcBatchResult { ok: false, results };
+            }
+            results[i].status = RcChangeStatus::Applied;
+        }
+
+        RcBatchResult { ok: true, results }
+    }
+
+    fn roll_back(&mut self, applied: &mut [RcChangeResult]) {
+        for r in applied.iter_mut().rev() {
+            let previous = r.previous.as_deref().unwrap_or_default();
+            r.status = match self.rc.set(&r.module, &r.param, previous) {
+                Ok(()) => RcChangeStatus::RolledBack,
+                Err(e) => {
+                    warn!("Cannot restore {}.{} to {}: {}", r.module, r.param, previous, e);
+                    RcChangeStatus::RollbackFailed { error: e.to_string() }
+                },
+            };
+        }
+    }
+}
@@ -0,0 +1,199 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Remote control over the line-based telnet interface of ODR-DabMux, for muxes built without
+//! ZMQ RC. Every answer, and the banner sent on connection, is followed by a prompt.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Instant,
+};
+use log::warn;
+use serde_json::{Map, Value};
+
+use super::{ClientSettings, Error, ModuleDescriptions, RemoteControl, Result};
+
+const PROMPT : &[u8] = b"> ";
+
+pub struct TelnetRemoteControl {
+    address : String,
+    settings : ClientSettings,
+    stream : Option<TcpStream>,
+}
+
+// Read until the prompt, and return what came before it
+fn read_answer(stream: &mut TcpStream, settings: &ClientSettings) -> Result<String> {
+    let deadline = Instant::now() + settings.timeout;
+    let mut answer = Vec::new();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::Timeout);
+        }
+        stream.set_read_timeout(Some(remaining))?;
+
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by the mux")));
+        }
+        answer.extend_from_slice(&buf[..n]);
+
+        // The prompt is alone on its line
+        if let Some(text) = answer.strip_suffix(PROMPT) {
+            if text.is_empty() || text.ends_with(b"\n") {
+                let len = text.len();
+                answer.truncate(len);
+                return Ok(String::from_utf8(answer)?);
+            }
+        }
+    }
+}
+
+// The mux answers a command it cannot carry out with an error message instead of the expected lines
+fn check_error(answer: String) -> Result<String> {
+    if answer.trim_start().starts_with("Error") {
+        Err(Error::Refused(answer.trim().to_owned()))
+    }
+    else {
+        Ok(answer)
+    }
+}
+
+// Split a `name : text` line of the list and show answers
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    line.split_once(':').map(|(name, text)| (name.trim(), text.trim()))
+}
+
+impl TelnetRemoteControl {
+    pub fn new(address: &str, settings: ClientSettings) -> Self {
+        Self { address: address.to_owned(), settings, stream: None }
+    }
+
+    fn connect(&self) -> Result<TcpStream> {
+        let addr = self.address.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::Io(io::Error::new(io::ErrorKind::NotFound, format!("cannot resolve {}", self.address))))?;
+
+        let mut stream = TcpStream::connect_timeout(&addr, self.settings.timeout)?;
+        stream.set_write_timeout(Some(self.settings.timeout))?;
+        stream.set_nodelay(true)?;
+
+        // Skip the banner
+        read_answer(&mut stream, &self.settings)?;
+        Ok(stream)
+    }
+
+    fn try_command(&mut self, command: &str) -> Result<String> {
+        if self.stream.is_none() {
+            self.stream = Some(self.connect()?);
+        }
+        let stream = self.stream.as_mut().unwrap();
+
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\n")?;
+        read_answer(stream, &self.settings)
+    }
+
+    /// Send a command and return the answer, reconnecting and retrying when the mux does not answer
+    fn command(&mut self, args: &[&str]) -> Result<String> {
+        if args.iter().any(|a| a.contains(['\r', '\n'])) {
+            return Err(Error::Protocol("line breaks cannot be sent over telnet".to_owned()));
+        }
+        let command = args.join(" ");
+
+        let mut result = Err(Error::Timeout);
+        for attempt in 0..=self.settings.retries {
+            result = self.try_command(&command);
+            match &result {
+                Ok(_) => break,
+                Err(e) => {
+                    warn!("No answer from {} to {} (attempt {}): {}, reconnecting", self.address, args[0], attempt + 1, e);
+                    self.stream = None;
+                },
+            }
+        }
+        result
+    }
+
+    fn show(&mut self, module: &str) -> Result<Map<String, Value>> {
+        let answer = check_error(self.command(&["show", module])?)?;
+
+        let mut values = Map::new();
+        for line in answer.lines().filter(|l| !l.trim().is_empty()) {
+            // Anything else than parameter values is an error message
+            let (param, value) = split_line(line).ok_or_else(|| Error::Refused(answer.trim().to_owned()))?;
+            values.insert(param.to_owned(), Value::String(value.to_owned()));
+        }
+        Ok(values)
+    }
+}
+
+impl RemoteControl for TelnetRemoteControl {
+    fn endpoint(&self) -> &str {
+        &self.address
+    }
+
+    fn show_all(&mut self) -> Result<Value> {
+        let mut modules : Vec<String> = self.list()?.into_keys().collect();
+        modules.sort();
+
+        let mut all = Map::new();
+        for module in modules {
+            let values = self.show(&module)?;
+            all.insert(module, Value::Object(values));
+        }
+        Ok(Value::Object(all))
+    }
+
+    fn list(&mut self) -> Result<HashMap<String, ModuleDescriptions>> {
+        // Module names, each followed by indented `param : description` lines
+        let answer = check_error(self.command(&["list"])?)?;
+
+        let mut descriptions : HashMap<String, ModuleDescriptions> = HashMap::new();
+        let mut current = None;
+        for line in answer.lines().filter(|l| !l.trim().is_empty()) {
+            if line.starts_with(char::is_whitespace) {
+                let module = current.as_ref()
+                    .ok_or_else(|| Error::Protocol(format!("RC list parameter before any module: {}", line.trim())))?;
+                let (param, desc) = split_line(line)
+                    .ok_or_else(|| Error::Protocol(format!("RC list line without description: {}", line.trim())))?;
+                descriptions.entry(String::clone(module)).or_default().push((param.to_owned(), desc.to_owned()));
+            }
+            else {
+                let module = line.trim().to_owned();
+                descriptions.entry(module.clone()).or_default();
+                current = Some(module);
+            }
+        }
+        Ok(descriptions)
+    }
+
+    fn get(&mut self, module: &str, param: &str) -> Result<String> {
+        let answer = check_error(self.command(&["get", module, param])?)?;
+        Ok(answer.trim_end_matches(['\r', '\n']).to_owned())
+    }
+
+    fn set(&mut self, module: &str, param: &str, value: &str) -> Result<()> {
+        let answer = self.command(&["set", module, param, value])?;
+        match answer.trim() {
+            "ok" => Ok(()),
+            // The mux answers with the error message
+            e => Err(Error::Refused(e.to_owned())),
+        }
+    }
+}
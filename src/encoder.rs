@@ -0,0 +1,116 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The encoders of a service: ODR-AudioEnc, and ODR-PadEnc when the service has PAD,
+//! started and stopped together.
+
+use serde::Serialize;
+
+use crate::muxlog::MuxLog;
+use crate::supervisor::{ProcessState, ProcessStatus, Supervisor, SupervisorSettings};
+
+/// How to run the encoders of a service
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderSettings {
+    pub audioenc : SupervisorSettings,
+    pub padenc : Option<SupervisorSettings>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncoderStatus {
+    pub audioenc : ProcessStatus,
+    pub padenc : Option<ProcessStatus>,
+}
+
+impl EncoderStatus {
+    /// The state of the process that is worst off, as both are needed for the service
+    pub fn state(&self) -> &ProcessState {
+        match &self.padenc {
+            Some(padenc) if matches!(self.audioenc.state, ProcessState::Running { .. }) => &padenc.state,
+            _ => &self.audioenc.state,
+        }
+    }
+
+    /// The processes with their names, as shown in the GUI
+    pub fn processes(&self) -> Vec<(&'static str, &ProcessStatus)> {
+        std::iter::once(("ODR-AudioEnc", &self.audioenc))
+            .chain(self.padenc.iter().map(|p| ("ODR-PadEnc", p)))
+            .collect()
+    }
+}
+
+/// Supervises the encoders of one service. They are killed when it is dropped.
+pub struct Encoder {
+    name : String,
+    audioenc : Supervisor,
+    padenc : Option<Supervisor>,
+    log : MuxLog,
+}
+
+impl Encoder {
+    /// Start supervising, without starting the encoders. Their output goes to the log.
+    pub fn spawn(name: &str, settings: EncoderSettings, log: MuxLog) -> Self {
+        Self {
+            name: name.to_owned(),
+            audioenc: Supervisor::spawn(&format!("{} audioenc", name), settings.audioenc, log.clone()),
+            padenc: settings.padenc.map(|s| Supervisor::spawn(&format!("{} padenc", name), s, log.clone())),
+            log,
+        }
+    }
+
+    fn supervisors(&self) -> impl Iterator<Item = &Supervisor> {
+        // ODR-AudioEnc connects to the socket of ODR-PadEnc, which starts first
+        self.padenc.iter().chain([&self.audioenc])
+    }
+
+    pub fn start(&self) {
+        self.supervisors().for_each(Supervisor::start);
+    }
+
+    pub fn stop(&self) {
+        self.supervisors().for_each(Supervisor::stop);
+    }
+
+    pub fn restart(&self) {
+        self.supervisors().for_each(Supervisor::restart);
+    }
+
+    /// Use other settings from the next start on. ODR-PadEnc is started right away when PAD is
+    /// added to a running encoder, and stopped when PAD is removed.
+    pub fn reconfigure(&mut self, settings: EncoderSettings) {
+        self.audioenc.reconfigure(settings.audioenc);
+        match (&self.padenc, settings.padenc) {
+            (Some(padenc), Some(s)) => padenc.reconfigure(s),
+            (None, Some(s)) => {
+                let padenc = Supervisor::spawn(&format!("{} padenc", self.name), s, self.log.clone());
+                if self.audioenc.status().state != ProcessState::Stopped {
+                    padenc.start();
+                }
+                self.padenc = Some(padenc);
+            },
+            (_, None) => self.padenc = None,
+        }
+    }
+
+    pub fn status(&self) -> EncoderStatus {
+        EncoderStatus {
+            audioenc: self.audioenc.status(),
+            padenc: self.padenc.as_ref().map(Supervisor::status),
+        }
+    }
+
+    pub fn log(&self) -> &MuxLog {
+        &self.log
+    }
+}
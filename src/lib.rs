@@ -0,0 +1,52 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The configuration model and mux client behind odr-dabmux-gui, for tools that create
+//! ensembles or monitor ODR-DabMux without the web UI.
+//!
+//! * [`Config`] describes one ensemble and its [`Service`]s, generates the ODR-DabMux JSON
+//!   configuration with [`Config::dabmux_json`] and checks itself with [`Config::validate`].
+//! * [`DabMux`] talks to the remote control and the management server of a running mux, and
+//!   [`DabMuxHandle`] does the same from async code.
+//! * [`poller::Poller`] keeps the latest stats and RC parameters of a mux.
+//! * [`supervisor::Supervisor`] runs odr-dabmux and restarts it when it exits, and
+//!   [`muxlog::MuxLog`] keeps its log output. [`encoder::Encoder`] does the same for the encoders of a service.
+//! * [`Config::changes_since`] tells which changes the remote control applies to a running mux and
+//!   which need a restart, that a [`scheduler::Schedule`] can plan for later. The schedule also holds
+//!   a staged configuration to switch to, and rolls back if the mux does not come back healthy.
+//!
+//! Build with `default-features = false` to leave out the web UI and its dependencies.
+//!
+//! ```no_run
+//! use odr_dabmux_gui::{Config, DabMux};
+//!
+//! let conf = Config::default();
+//! std::fs::write(&conf.dabmux_config_location, conf.dabmux_json().to_string()).unwrap();
+//!
+//! let mut mux = DabMux::new(&conf.rc_endpoint(), &conf.stats_endpoint(), conf.client_settings());
+//! for (ident, input) in mux.get_stats().unwrap().input_stats {
+//!     println!("{}: {:?}", ident, input.state);
+//! }
+//! ```
+
+pub mod config;
+pub mod dabmux;
+pub mod encoder;
+pub mod muxlog;
+pub mod poller;
+pub mod scheduler;
+pub mod supervisor;
+
+pub use config::{Config, Service, Validation};
+pub use dabmux::{DabMux, DabMuxHandle, Param, ParamKind, Stats};
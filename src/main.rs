@@ -12,17 +12,302 @@
  * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::sync::{Arc, Mutex};
-use log::info;
+use std::{collections::BTreeMap, sync::{Arc, Mutex}, time::Duration};
+use chrono::{DateTime, Utc};
+use log::{error, info};
 use argparse::{ArgumentParser, Store};
 
+use odr_dabmux_gui::{config, dabmux, encoder, muxlog, poller, scheduler, supervisor};
+use odr_dabmux_gui::muxlog::LogLevel;
+
 mod ui;
-mod config;
-mod dabmux;
 
-struct AppState {
+struct Instance {
     conf : config::Config,
-    dabmux : dabmux::DabMux,
+    dabmux : dabmux::DabMuxHandle,
+    poller : poller::Poller,
+    supervisor : supervisor::Supervisor,
+    log : muxlog::MuxLog,
+    tail : Option<muxlog::FileTail>,
+    /// Encoders of the services the GUI runs, by unique id
+    encoders : BTreeMap<String, encoder::Encoder>,
+    /// The configuration the mux runs, as far as the GUI knows: the saved one when the mux
+    /// started, with the changes applied through the remote control since
+    running_conf : config::Config,
+    saved_at : DateTime<Utc>,
+    schedule : scheduler::Schedule,
+}
+
+fn spawn_tail(conf: &config::Config, log: &muxlog::MuxLog) -> Option<muxlog::FileTail> {
+    if conf.process.log_file.is_empty() {
+        None
+    }
+    else {
+        Some(muxlog::FileTail::spawn(conf.process.log_file.clone().into(), log.clone()))
+    }
+}
+
+impl Instance {
+    /// Set up an instance with its schedule, as stored with the GUI configuration
+    fn new(conf: config::Config, schedule: scheduler::Schedule) -> Self {
+        let dabmux = dabmux::DabMux::new(&conf.rc_endpoint(), &conf.stats_endpoint(), conf.client_settings());
+        let dabmux = dabmux::DabMuxHandle::spawn(&conf.instance_name, dabmux);
+        let poller = poller::Poller::spawn(dabmux.clone(), conf.poll_interval(), conf.dabmux_config_location.clone().into());
+        let log = muxlog::MuxLog::default();
+        let supervisor = supervisor::Supervisor::spawn(&conf.instance_name, conf.supervisor_settings(), log.clone());
+        let tail = spawn_tail(&conf, &log);
+        let mut inst = Self {
+            running_conf: conf.clone(),
+            conf, dabmux, poller, supervisor, log, tail,
+            encoders: BTreeMap::new(),
+            saved_at: Utc::now(),
+            schedule,
+        };
+        inst.sync_encoders();
+        for missed in inst.schedule.missed(Utc::now()) {
+            inst.target().report(LogLevel::Error, &format!("Scheduled action missed at {}: {}",
+                    missed.at.format("%Y-%m-%d %H:%M:%S UTC"), missed.action.description()));
+        }
+        inst
+    }
+
+    /// Supervise the encoders of the enabled services the GUI runs, and stop the others.
+    /// Running encoders use changed settings when they are restarted.
+    fn sync_encoders(&mut self) {
+        let conf = &self.conf;
+        let managed = || conf.enabled_services().filter(|s| s.encoder.managed);
+        self.encoders.retain(|uid, _| managed().any(|s| s.unique_id == *uid));
+        for s in managed() {
+            let settings = s.encoder_settings(conf);
+            match self.encoders.get_mut(&s.unique_id) {
+                Some(enc) => enc.reconfigure(settings),
+                None => {
+                    let name = format!("{}/{}", conf.instance_name, s.unique_id);
+                    self.encoders.insert(s.unique_id.clone(), encoder::Encoder::spawn(&name, settings, muxlog::MuxLog::default()));
+                },
+            }
+        }
+    }
+
+    /// When the mux started, if it runs
+    fn mux_started(&self) -> Option<DateTime<Utc>> {
+        if let supervisor::ProcessState::Running { since, .. } = self.supervisor.status().state {
+            return Some(since);
+        }
+        // A mux started outside the GUI tells its uptime
+        let snapshot = self.poller.snapshot();
+        let (Some(stats), Some(updated)) = (&snapshot.stats.value, snapshot.stats.updated) else {
+            return None;
+        };
+        stats.uptime.map(|uptime| updated - chrono::Duration::seconds(uptime as i64))
+    }
+
+    fn restarted_since_save(&self) -> bool {
+        self.mux_started().is_some_and(|started| started >= self.saved_at)
+    }
+
+    /// Call before the saved configuration changes
+    fn saving(&mut self) {
+        if self.restarted_since_save() {
+            self.running_conf = self.conf.clone();
+        }
+        self.saved_at = Utc::now();
+    }
+
+    /// The changes the running mux lacks, of those saved since it started
+    fn pending_changes(&self) -> config::changes::Changes {
+        if self.restarted_since_save() {
+            Default::default()
+        }
+        else {
+            self.conf.changes_since(&self.running_conf)
+        }
+    }
+
+    /// Record a value the remote control set on the running mux
+    fn applied_live(&mut self, module: &str, param: &str, value: &str) {
+        // Not every RC parameter is part of the configuration
+        let _ = self.running_conf.set_rc_value(module, param, value);
+    }
+
+    /// Use a new configuration. The client reconnects in case the ports changed, but odr-dabmux
+    /// keeps running until it is restarted.
+    fn reconfigure(&mut self, conf: config::Config) {
+        self.saving();
+        let dabmux = dabmux::DabMux::new(&conf.rc_endpoint(), &conf.stats_endpoint(), conf.client_settings());
+        self.dabmux = dabmux::DabMuxHandle::spawn(&conf.instance_name, dabmux);
+        self.poller = poller::Poller::spawn(self.dabmux.clone(), conf.poll_interval(), conf.dabmux_config_location.clone().into());
+        self.supervisor.reconfigure(conf.supervisor_settings());
+        if conf.process.log_file != self.conf.process.log_file {
+            self.tail = spawn_tail(&conf, &self.log);
+        }
+        self.conf = conf;
+        self.sync_encoders();
+    }
+
+    /// Write the generated configuration, then start odr-dabmux, or restart it if it is running
+    fn start_process(&self, restart: bool) -> anyhow::Result<()> {
+        self.conf.write_dabmux_json()?;
+        if restart {
+            self.supervisor.restart();
+        }
+        else {
+            self.supervisor.start();
+        }
+        Ok(())
+    }
+
+    /// The mux the schedule of this instance acts on
+    fn target(&self) -> scheduler::Target<'_> {
+        scheduler::Target {
+            name: &self.conf.instance_name,
+            supervisor: &self.supervisor,
+            poller: &self.poller,
+            log: &self.log,
+        }
+    }
+
+    /// Run the schedule, and switch to the configuration of the restarts it decides on
+    fn run_schedule(&mut self, others: &[&config::Config], now: DateTime<Utc>) -> scheduler::Step {
+        // Taken out while the target borrows the instance
+        let mut schedule = std::mem::take(&mut self.schedule);
+        let step = schedule.run(&self.target(), &self.conf, others, now);
+        self.schedule = schedule;
+        for restart in step.restarts.iter().filter(|r| r.switches()) {
+            self.reconfigure(restart.conf.clone());
+        }
+        step
+    }
+
+    /// Record the outcome of a restart decided by the schedule, and switch back to the
+    /// previous configuration if it needs to be restored
+    fn restarted(&mut self, restart: scheduler::Restart, result: anyhow::Result<()>) -> Option<scheduler::Restart> {
+        let mut schedule = std::mem::take(&mut self.schedule);
+        let rollback = schedule.restarted(&self.target(), restart, result);
+        self.schedule = schedule;
+        let rollback = rollback?;
+        self.reconfigure(rollback.conf.clone());
+        Some(rollback)
+    }
+}
+
+/// A restart decided by the schedule of an instance, with the supervisor that carries it out
+type PlannedRestart = (String, supervisor::Supervisor, scheduler::Restart);
+
+/// Carry out the scheduled actions of all instances when they are due, and check the
+/// mux after a change of configuration. The configuration of odr-dabmux is written and the
+/// process restarted without holding the lock.
+async fn run_schedules(state: SharedState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let mut restarts = state.lock().unwrap().run_schedules(Utc::now());
+        while !restarts.is_empty() {
+            let results : Vec<_> = restarts.into_iter()
+                .map(|(name, supervisor, restart)| {
+                    let result = restart.run(&supervisor);
+                    (name, restart, result)
+                })
+                .collect();
+            restarts = state.lock().unwrap().restarted(results);
+        }
+    }
+}
+
+struct AppState {
+    instances : Vec<Instance>,
+    templates : config::templates::Templates,
+}
+
+impl AppState {
+    fn instance(&self, name: &str) -> Option<&Instance> {
+        self.instances.iter().find(|i| i.conf.instance_name == name)
+    }
+
+    fn instance_mut(&mut self, name: &str) -> Option<&mut Instance> {
+        self.instances.iter_mut().find(|i| i.conf.instance_name == name)
+    }
+
+    fn instance_names(&self) -> Vec<String> {
+        self.instances.iter().map(|i| i.conf.instance_name.clone()).collect()
+    }
+
+    /// Configurations of all instances except the one with the given name
+    fn other_confs(&self, name: &str) -> Vec<&config::Config> {
+        self.instances.iter()
+            .map(|i| &i.conf)
+            .filter(|c| c.instance_name != name)
+            .collect()
+    }
+
+    fn gui_config(&self) -> config::GuiConfig {
+        config::GuiConfig {
+            instances: self.instances.iter().map(|i| i.conf.clone()).collect(),
+            templates: self.templates.clone(),
+            schedules: self.instances.iter()
+                .map(|i| (i.conf.instance_name.clone(), i.schedule.clone()))
+                .collect(),
+        }
+    }
+
+    fn store(&self) -> anyhow::Result<()> {
+        self.gui_config().store()
+    }
+
+    fn store_schedules(&self) {
+        if let Err(e) = self.store() {
+            error!("Failed to write UI config: {:#}", e);
+        }
+    }
+
+    /// Run the schedules of all instances, returning the restarts they decided on
+    fn run_schedules(&mut self, now: DateTime<Utc>) -> Vec<PlannedRestart> {
+        let mut restarts = Vec::new();
+        let mut changed = false;
+        for i in 0..self.instances.len() {
+            let (before, rest) = self.instances.split_at_mut(i);
+            let Some((inst, after)) = rest.split_first_mut() else {
+                break;
+            };
+            let others : Vec<&config::Config> = before.iter().chain(after.iter()).map(|o| &o.conf).collect();
+            let step = inst.run_schedule(&others, now);
+            changed |= step.changed;
+            for restart in step.restarts {
+                restarts.push((inst.conf.instance_name.clone(), inst.supervisor.clone(), restart));
+            }
+        }
+        if changed {
+            self.store_schedules();
+        }
+        restarts
+    }
+
+    /// Record the outcome of restarts, returning those that roll back a failed change
+    fn restarted(&mut self, results: Vec<(String, scheduler::Restart, anyhow::Result<()>)>) -> Vec<PlannedRestart> {
+        let mut rollbacks = Vec::new();
+        for (name, restart, result) in results {
+            let Some(inst) = self.instance_mut(&name) else {
+                continue;
+            };
+            if let Some(rollback) = inst.restarted(restart, result) {
+                rollbacks.push((name, inst.supervisor.clone(), rollback));
+            }
+        }
+        self.store_schedules();
+        rollbacks
+    }
+
+    /// Store a new configuration of instance `name`, before the instance uses it
+    fn store_with(&self, name: &str, conf: &config::Config) -> anyhow::Result<()> {
+        let mut gui_conf = self.gui_config();
+        if let Some(c) = gui_conf.instances.iter_mut().find(|c| c.instance_name == name) {
+            *c = conf.clone();
+        }
+        if let Some(schedule) = gui_conf.schedules.remove(name) {
+            gui_conf.schedules.insert(conf.instance_name.clone(), schedule);
+        }
+        gui_conf.store()
+    }
 }
 
 type SharedState = Arc<Mutex<AppState>>;
@@ -34,22 +319,62 @@ async fn main() -> std::io::Result<()> {
         .env()
         .init().unwrap();
 
-    let conf = config::Config::load().expect("Could not load config");
-
-    let shared_state = Arc::new(Mutex::new(AppState {
-        conf : conf.clone(),
-        dabmux : dabmux::DabMux::new(),
-    }));
-
     let mut port = 3000;
+    let mut write_encoders = String::new();
     {
         let mut ap = ArgumentParser::new();
         ap.set_description("odr dabmux gui");
         ap.refer(&mut port)
             .add_option(&["-p", "--port"], Store, "web gui port number");
+        ap.refer(&mut write_encoders)
+            .add_option(&["--write-encoders"], Store,
+                "write encoder command lines and systemd units of all instances to this directory, then exit");
         ap.parse_args_or_exit();
     }
 
+    let gui_conf = config::GuiConfig::load().expect("Could not load config");
+
+    // Only writes files, without connecting to or running anything
+    if !write_encoders.is_empty() {
+        for conf in &gui_conf.instances {
+            let dir = std::path::Path::new(&write_encoders).join(&conf.instance_name);
+            match conf.write_encoder_files(&dir) {
+                Ok(()) => info!("Wrote encoder files to {}", dir.display()),
+                Err(e) => {
+                    error!("Failed to write encoder files: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let mut schedules = gui_conf.schedules;
+    let shared_state = Arc::new(Mutex::new(AppState {
+        instances : gui_conf.instances.into_iter()
+            .map(|conf| {
+                let schedule = schedules.remove(&conf.instance_name).unwrap_or_default();
+                Instance::new(conf, schedule)
+            })
+            .collect(),
+        templates : gui_conf.templates,
+    }));
+
+    for inst in &shared_state.lock().unwrap().instances {
+        if inst.conf.process.autostart {
+            if let Err(e) = inst.start_process(false) {
+                error!("Cannot start odr-dabmux for {}: {:#}", inst.conf.instance_name, e);
+            }
+        }
+        for s in inst.conf.enabled_services().filter(|s| s.encoder.autostart) {
+            if let Some(enc) = inst.encoders.get(&s.unique_id) {
+                enc.start();
+            }
+        }
+    }
+
+    tokio::spawn(run_schedules(shared_state.clone()));
+
     info!("Setting up listener on port {port}");
     ui::serve(port, shared_state).await;
     Ok(())
@@ -0,0 +1,306 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The log output of a mux, captured from the supervised process or read from a log file,
+//! kept in a bounded buffer and broadcast to the log viewers.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    sync::broadcast,
+};
+
+/// Number of lines kept
+pub const LOG_LINES : usize = 2000;
+/// Number of lines a slow viewer may lag behind before it misses some
+const BROADCAST_CAPACITY : usize = 256;
+/// How often a log file is checked for new lines
+const TAIL_INTERVAL : Duration = Duration::from_millis(500);
+/// Most of a log file read at once: the end of an existing file when the GUI starts tailing it,
+/// or of lines written faster than they are read
+const TAIL_BACKLOG : u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// The level of a line written by odr-dabmux, which starts with the level, possibly after a timestamp
+    pub fn of(text: &str) -> Option<Self> {
+        for word in text.split_whitespace().take(4) {
+            let word = word.trim_matches(|c: char| !c.is_ascii_alphabetic()).to_ascii_uppercase();
+            match word.as_str() {
+                "TRACE" | "DEBUG" => return Some(LogLevel::Debug),
+                "INFO" => return Some(LogLevel::Info),
+                "WARN" | "WARNING" => return Some(LogLevel::Warn),
+                "ERROR" | "ALERT" | "EMERG" | "CRIT" => return Some(LogLevel::Error),
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogLevel::Debug => write!(f, "DEBUG"),
+            LogLevel::Info => write!(f, "INFO"),
+            LogLevel::Warn => write!(f, "WARN"),
+            LogLevel::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSource {
+    Stdout,
+    Stderr,
+    File,
+    /// Messages of the GUI about the process, e.g. that it exited
+    Supervisor,
+}
+
+impl fmt::Display for LogSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogSource::Stdout => write!(f, "stdout"),
+            LogSource::Stderr => write!(f, "stderr"),
+            LogSource::File => write!(f, "file"),
+            LogSource::Supervisor => write!(f, "supervisor"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    /// Increases by one for every line, so that viewers notice the lines they missed
+    pub seq : u64,
+    pub time : DateTime<Utc>,
+    pub source : LogSource,
+    pub level : LogLevel,
+    pub text : String,
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}] {} {}", self.time.format("%Y-%m-%d %H:%M:%S%.3f"), self.source, self.level, self.text)
+    }
+}
+
+struct Lines {
+    lines : VecDeque<LogLine>,
+    next_seq : u64,
+}
+
+/// The last LOG_LINES lines of a mux. Clones share the same buffer.
+#[derive(Clone)]
+pub struct MuxLog {
+    lines : Arc<Mutex<Lines>>,
+    tx : broadcast::Sender<LogLine>,
+}
+
+impl Default for MuxLog {
+    fn default() -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(Lines { lines: VecDeque::new(), next_seq: 0 })),
+            tx: broadcast::channel(BROADCAST_CAPACITY).0,
+        }
+    }
+}
+
+impl MuxLog {
+    /// Add a line, with the level given in its text, or info if there is none
+    pub fn push(&self, source: LogSource, text: &str) {
+        self.push_level(source, LogLevel::of(text).unwrap_or(LogLevel::Info), text);
+    }
+
+    pub fn push_level(&self, source: LogSource, level: LogLevel, text: &str) {
+        let mut lines = self.lines.lock().unwrap();
+        let line = LogLine {
+            seq: lines.next_seq,
+            time: Utc::now(),
+            source,
+            level,
+            text: text.trim_end_matches(['\r', '\n']).to_owned(),
+        };
+        lines.next_seq += 1;
+        if lines.lines.len() == LOG_LINES {
+            lines.lines.pop_front();
+        }
+        lines.lines.push_back(line.clone());
+        // Sent under the lock, so that subscribe never misses or repeats a line. Fails without viewers.
+        let _ = self.tx.send(line);
+    }
+
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.lines.lock().unwrap().lines.iter().cloned().collect()
+    }
+
+    /// The lines so far, and a receiver for the following ones
+    pub fn subscribe(&self) -> (Vec<LogLine>, broadcast::Receiver<LogLine>) {
+        let lines = self.lines.lock().unwrap();
+        (lines.lines.iter().cloned().collect(), self.tx.subscribe())
+    }
+
+    /// Add every line read from a pipe of the process, until it is closed
+    pub async fn capture(self, source: LogSource, reader: impl AsyncRead + Unpin) {
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => self.push(source, &String::from_utf8_lossy(&buf)),
+            }
+        }
+    }
+}
+
+/// Follows a log file, like `tail -F`, adding its new lines to a MuxLog. Stops when dropped.
+pub struct FileTail {
+    pub path : PathBuf,
+    task : tokio::task::JoinHandle<()>,
+}
+
+struct TailState {
+    path : PathBuf,
+    pos : u64,
+    /// Identifies the file, to notice when it is replaced by log rotation
+    id : Option<(u64, u64)>,
+    /// Start of a line that is not complete yet
+    partial : Vec<u8>,
+    /// Reading started in the middle of a line, which is not shown
+    skip_first_line : bool,
+}
+
+#[cfg(unix)]
+fn file_id(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+impl TailState {
+    fn read_new_lines(&mut self, log: &MuxLog) -> std::io::Result<()> {
+        let meta = fs::metadata(&self.path)?;
+        let id = file_id(&meta);
+        if id != self.id || meta.len() < self.pos {
+            // Rotated or truncated, read the new file from the start
+            self.pos = 0;
+            self.partial.clear();
+            self.skip_first_line = false;
+            self.id = id;
+        }
+        if meta.len() == self.pos {
+            return Ok(());
+        }
+        // Show only the end of a long existing file, or of a burst of lines
+        if meta.len() - self.pos > TAIL_BACKLOG {
+            if self.pos > 0 {
+                log.push_level(LogSource::Supervisor, LogLevel::Warn,
+                    &format!("Skipped {} bytes of log file {}", meta.len() - TAIL_BACKLOG - self.pos, self.path.display()));
+            }
+            self.pos = meta.len() - TAIL_BACKLOG;
+            self.partial.clear();
+            self.skip_first_line = true;
+        }
+
+        let mut file = fs::File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.pos))?;
+        let mut data = Vec::new();
+        // The file may have grown since
+        file.take(TAIL_BACKLOG).read_to_end(&mut data)?;
+        self.pos += data.len() as u64;
+
+        self.partial.extend_from_slice(&data);
+        if self.skip_first_line {
+            match self.partial.iter().position(|b| *b == b'\n') {
+                Some(end) => {
+                    self.partial.drain(..=end);
+                    self.skip_first_line = false;
+                },
+                None => return Ok(()),
+            }
+        }
+        if let Some(end) = self.partial.iter().rposition(|b| *b == b'\n') {
+            let complete : Vec<u8> = self.partial.drain(..=end).collect();
+            for line in String::from_utf8_lossy(&complete).lines() {
+                log.push(LogSource::File, line);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FileTail {
+    pub fn spawn(path: PathBuf, log: MuxLog) -> Self {
+        let mut state = TailState { path: path.clone(), pos: 0, id: None, partial: Vec::new(), skip_first_line: false };
+
+        let task = tokio::spawn(async move {
+            let mut missing = false;
+            loop {
+                // File access blocks
+                let reader_log = log.clone();
+                let read = tokio::task::spawn_blocking(move || {
+                    let result = state.read_new_lines(&reader_log);
+                    (state, result)
+                }).await;
+                let Ok((returned, result)) = read else {
+                    return;
+                };
+                state = returned;
+                match result {
+                    Ok(()) => missing = false,
+                    Err(e) => {
+                        if !missing {
+                            log.push_level(LogSource::Supervisor, LogLevel::Warn,
+                                &format!("Cannot read log file {}: {}", state.path.display(), e));
+                        }
+                        missing = true;
+                        state.id = None;
+                    },
+                }
+                tokio::time::sleep(TAIL_INTERVAL).await;
+            }
+        });
+        Self { path, task }
+    }
+}
+
+impl Drop for FileTail {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -0,0 +1,163 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Background polling of the stats and RC parameters of a mux, so that pages and APIs can be
+//! served from the latest snapshot instead of waiting for the mux.
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{watch, Notify};
+
+use crate::dabmux::{self, DabMuxHandle, Param, Stats};
+
+/// The last value successfully read from the mux, and the error of the last attempt if it failed
+#[derive(Debug, Clone, Serialize)]
+pub struct Cached<T> {
+    pub value : Option<T>,
+    pub updated : Option<DateTime<Utc>>,
+    pub error : Option<String>,
+    pub error_time : Option<DateTime<Utc>>,
+}
+
+impl<T> Default for Cached<T> {
+    fn default() -> Self {
+        Self { value: None, updated: None, error: None, error_time: None }
+    }
+}
+
+impl<T: Clone> Cached<T> {
+    /// The cache after a new attempt to read the value. The previous value is kept when the attempt failed.
+    fn next(&self, result: dabmux::Result<T>) -> Self {
+        let now = Utc::now();
+        match result {
+            Ok(v) => Self { value: Some(v), updated: Some(now), error: None, error_time: None },
+            Err(e) => Self {
+                value: self.value.clone(),
+                updated: self.updated,
+                error: Some(e.to_string()),
+                error_time: Some(now),
+            },
+        }
+    }
+}
+
+impl<T> Cached<T> {
+    /// Age of the value in seconds
+    pub fn age(&self) -> Option<i64> {
+        self.updated.map(|t| (Utc::now() - t).num_seconds())
+    }
+
+    pub fn age_str(&self) -> String {
+        match self.age() {
+            Some(age) => format!("{} s ago", age),
+            None => "never".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Snapshot {
+    pub stats : Cached<Stats>,
+    pub params : Cached<Vec<Param>>,
+    /// The configuration the mux is running, compared to the saved one by the UI
+    #[serde(skip)]
+    pub running_config : Cached<Value>,
+    /// The configuration file odr-dabmux reads when it starts, None if it cannot be read
+    #[serde(skip)]
+    pub config_file : Option<Value>,
+}
+
+async fn read_config_file(path: &PathBuf) -> Option<Value> {
+    let contents = tokio::fs::read_to_string(path).await.ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Polls a mux until dropped
+pub struct Poller {
+    snapshot : watch::Receiver<Arc<Snapshot>>,
+    refresh : Arc<Notify>,
+    task : tokio::task::JoinHandle<()>,
+}
+
+impl Poller {
+    /// Poll the mux, and read `config_file` to tell if it is up to date
+    pub fn spawn(dabmux: DabMuxHandle, interval: Duration, config_file: PathBuf) -> Self {
+        let (tx, snapshot) = watch::channel(Arc::new(Snapshot::default()));
+        let refresh = Arc::new(Notify::new());
+
+        let notified = refresh.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                // Publish each result as soon as it is known, so that an unreachable mux
+                // shows up after one timeout
+                let stats = dabmux.get_stats().await;
+                let reachable = stats.is_ok();
+                tx.send_modify(|s| *s = Arc::new(Snapshot {
+                    stats: s.stats.next(stats),
+                    ..Snapshot::clone(s)
+                }));
+
+                let params = dabmux.get_rc_parameters().await;
+                tx.send_modify(|s| *s = Arc::new(Snapshot {
+                    params: s.params.next(params),
+                    ..Snapshot::clone(s)
+                }));
+
+                // Avoid waiting for another timeout when the management server is down
+                if reachable {
+                    let running_config = dabmux.get_running_config().await;
+                    tx.send_modify(|s| *s = Arc::new(Snapshot {
+                        running_config: s.running_config.next(running_config),
+                        ..Snapshot::clone(s)
+                    }));
+
+                    let file = read_config_file(&config_file).await;
+                    tx.send_modify(|s| *s = Arc::new(Snapshot {
+                        config_file: file,
+                        ..Snapshot::clone(s)
+                    }));
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => (),
+                    _ = notified.notified() => (),
+                }
+            }
+        });
+
+        Self { snapshot, refresh, task }
+    }
+
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.borrow().clone()
+    }
+
+    /// A receiver that is notified of every new snapshot. It is closed when the poller is dropped.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Snapshot>> {
+        self.snapshot.clone()
+    }
+
+    /// Poll again now, e.g. after changing an RC parameter
+    pub fn refresh(&self) {
+        self.refresh.notify_one();
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
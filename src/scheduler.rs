@@ -0,0 +1,421 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Actions on a mux planned for a given time, e.g. a restart at midnight to apply changes
+//! that the remote control cannot apply, or to switch to a staged configuration.
+//!
+//! After switching to a staged configuration, the mux has to come back healthy within a timeout,
+//! otherwise the previous configuration is restored.
+//!
+//! [`Schedule::run`] decides what to do, and returns the [`Restart`]s it needs. These write files
+//! and restart processes, so they are carried out separately, e.g. without holding a lock, and
+//! their outcome given back to [`Schedule::restarted`].
+//!
+//! Schedules are stored with the GUI configuration, so that they survive a restart of the GUI.
+
+use std::collections::VecDeque;
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::muxlog::{LogLevel, LogSource, MuxLog};
+use crate::poller::Poller;
+use crate::supervisor::{ProcessState, Supervisor};
+
+/// Number of finished actions kept in the history
+const HISTORY_KEPT : usize = 20;
+
+fn default_health_timeout() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Write the saved configuration and restart the mux
+    Restart,
+    /// Save the staged configuration, write it and restart the mux. Roll back if the mux is
+    /// not healthy after the timeout.
+    ApplyStaged {
+        #[serde(default = "default_health_timeout")]
+        health_timeout_s : u64,
+    },
+}
+
+impl Action {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Restart => "Restart ODR-DabMux with the saved configuration",
+            Action::ApplyStaged { .. } => "Apply the staged configuration and restart ODR-DabMux",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledAction {
+    pub id : u64,
+    pub at : DateTime<Utc>,
+    #[serde(flatten)]
+    pub action : Action,
+}
+
+/// A configuration waiting to be applied by an `ApplyStaged` action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Staged {
+    pub conf : Config,
+    pub staged_at : DateTime<Utc>,
+}
+
+/// What the GUI knows about the mux since it was restarted
+#[derive(Debug, Clone, PartialEq)]
+pub enum Health {
+    /// Not running or not answering yet
+    Starting,
+    /// Runs and answers the management server
+    Healthy,
+    /// Exited or could not be started
+    Failed(String),
+}
+
+/// A restart with a new configuration, waiting for the mux to come back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Verification {
+    pub id : u64,
+    #[serde(flatten)]
+    pub action : Action,
+    pub started : DateTime<Utc>,
+    pub deadline : DateTime<Utc>,
+    /// Restored if the mux does not come back healthy
+    pub previous : Config,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ActionResult {
+    Done,
+    Failed { error: String },
+    /// The previous configuration was restored
+    RolledBack { reason: String },
+}
+
+impl ActionResult {
+    pub fn description(&self) -> String {
+        match self {
+            ActionResult::Done => "Done".to_owned(),
+            ActionResult::Failed { error } => format!("Failed: {}", error),
+            ActionResult::RolledBack { reason } => format!("Rolled back: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinishedAction {
+    pub id : u64,
+    #[serde(flatten)]
+    pub action : Action,
+    pub finished : DateTime<Utc>,
+    #[serde(flatten)]
+    pub result : ActionResult,
+}
+
+/// The mux a schedule acts on
+pub struct Target<'a> {
+    pub name : &'a str,
+    pub supervisor : &'a Supervisor,
+    pub poller : &'a Poller,
+    pub log : &'a MuxLog,
+}
+
+impl Target<'_> {
+    /// Log an event of the scheduler, in the GUI log and next to the output of odr-dabmux
+    pub fn report(&self, level: LogLevel, message: &str) {
+        match level {
+            LogLevel::Error => error!("{}: {}", self.name, message),
+            LogLevel::Warn => warn!("{}: {}", self.name, message),
+            _ => info!("{}: {}", self.name, message),
+        }
+        self.log.push_level(LogSource::Supervisor, level, message);
+    }
+
+    /// How odr-dabmux fares since it was restarted at `restarted`
+    pub fn health_since(&self, restarted: DateTime<Utc>) -> Health {
+        let status = self.supervisor.status();
+        if let Some(exit) = status.exits.iter().find(|e| e.time >= restarted && !e.requested) {
+            return Health::Failed(format!("ODR-DabMux exited with {}", exit.description()));
+        }
+        match status.state {
+            ProcessState::Failed { error } => Health::Failed(error),
+            // Answering the management server after it started
+            ProcessState::Running { since, .. } if since >= restarted => {
+                let snapshot = self.poller.snapshot();
+                match (&snapshot.stats.value, snapshot.stats.updated) {
+                    (Some(_), Some(updated)) if updated >= since => Health::Healthy,
+                    _ => Health::Starting,
+                }
+            },
+            _ => Health::Starting,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Cause {
+    /// A scheduled restart with the configuration of the instance
+    Scheduled(ScheduledAction),
+    /// Switching to the staged configuration, which is being verified
+    Apply,
+    /// Going back to the configuration used before the staged one
+    Rollback,
+}
+
+/// A restart of odr-dabmux decided by the schedule. The instance uses the configuration of the
+/// restart before it is carried out.
+#[derive(Debug, Clone)]
+pub struct Restart {
+    pub conf : Config,
+    cause : Cause,
+}
+
+impl Restart {
+    /// Whether the instance switches to another configuration
+    pub fn switches(&self) -> bool {
+        !matches!(self.cause, Cause::Scheduled(_))
+    }
+
+    /// Write the configuration, then restart odr-dabmux with it
+    pub fn run(&self, supervisor: &Supervisor) -> anyhow::Result<()> {
+        supervisor.reconfigure(self.conf.supervisor_settings());
+        self.conf.write_dabmux_json()?;
+        supervisor.restart();
+        Ok(())
+    }
+}
+
+/// What a run of the schedule decided
+#[derive(Debug, Default)]
+pub struct Step {
+    /// To carry out in order
+    pub restarts : Vec<Restart>,
+    /// The schedule changed, and should be stored
+    pub changed : bool,
+}
+
+/// The actions planned for one mux
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Schedule {
+    next_id : u64,
+    /// Sorted by time
+    actions : Vec<ScheduledAction>,
+    staged : Option<Staged>,
+    verifying : Option<Verification>,
+    /// Most recent first
+    history : VecDeque<FinishedAction>,
+}
+
+impl Schedule {
+    /// Plan an action, returning its id
+    pub fn add(&mut self, at: DateTime<Utc>, action: Action) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let index = self.actions.partition_point(|a| a.at <= at);
+        self.actions.insert(index, ScheduledAction { id, at, action });
+        id
+    }
+
+    /// Remove a planned action. Returns false if there is none with this id.
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let len = self.actions.len();
+        self.actions.retain(|a| a.id != id);
+        self.actions.len() != len
+    }
+
+    pub fn upcoming(&self) -> &[ScheduledAction] {
+        &self.actions
+    }
+
+    /// Remove and return the actions whose time has come, oldest first
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<ScheduledAction> {
+        let due = self.actions.partition_point(|a| a.at <= now);
+        self.actions.drain(..due).collect()
+    }
+
+    /// Record the actions whose time passed while the GUI was not running as failed, instead of
+    /// carrying them out late, and return them. Call when the schedule is loaded.
+    pub fn missed(&mut self, now: DateTime<Utc>) -> Vec<ScheduledAction> {
+        let missed = self.take_due(now);
+        for scheduled in &missed {
+            let error = format!("the GUI was not running at {}", scheduled.at.format("%Y-%m-%d %H:%M:%S UTC"));
+            self.finished(scheduled.id, scheduled.action.clone(), ActionResult::Failed { error });
+        }
+        missed
+    }
+
+    pub fn stage(&mut self, conf: Config) {
+        self.staged = Some(Staged { conf, staged_at: Utc::now() });
+    }
+
+    /// Drop the staged configuration. Returns false if there was none.
+    pub fn unstage(&mut self) -> bool {
+        self.staged.take().is_some()
+    }
+
+    pub fn staged(&self) -> Option<&Staged> {
+        self.staged.as_ref()
+    }
+
+    /// Take the staged configuration to apply it
+    pub fn take_staged(&mut self) -> Option<Config> {
+        self.staged.take().map(|s| s.conf)
+    }
+
+    /// Wait for the mux to come back healthy after a restart with a new configuration
+    pub fn verify(&mut self, scheduled: &ScheduledAction, previous: Config, started: DateTime<Utc>, timeout: Duration) {
+        self.verifying = Some(Verification {
+            id: scheduled.id,
+            action: scheduled.action.clone(),
+            started,
+            deadline: started + timeout,
+            previous,
+        });
+    }
+
+    pub fn verifying(&self) -> Option<&Verification> {
+        self.verifying.as_ref()
+    }
+
+    /// Check the health of the mux being verified. Returns the configuration to restore
+    /// if it failed or did not become healthy in time.
+    pub fn check(&mut self, health: Health, now: DateTime<Utc>) -> Option<Config> {
+        let v = self.verifying.as_ref()?;
+        let result = match health {
+            Health::Healthy => ActionResult::Done,
+            Health::Failed(reason) => ActionResult::RolledBack { reason },
+            Health::Starting if now >= v.deadline => ActionResult::RolledBack {
+                reason: format!("not healthy after {} s", (v.deadline - v.started).num_seconds()),
+            },
+            Health::Starting => return None,
+        };
+        let v = self.verifying.take()?;
+        let rollback = matches!(result, ActionResult::RolledBack { .. });
+        self.finished(v.id, v.action, result);
+        rollback.then_some(v.previous)
+    }
+
+    /// Check the mux being verified, then start the actions that are due. `conf` is the configuration
+    /// of the instance, and `others` those of the other instances.
+    pub fn run(&mut self, target: &Target, conf: &Config, others: &[&Config], now: DateTime<Utc>) -> Step {
+        let mut step = Step::default();
+
+        if let Some(started) = self.verifying().map(|v| v.started) {
+            match self.check(target.health_since(started), now) {
+                Some(previous) => {
+                    if let Some(f) = self.history().front() {
+                        target.report(LogLevel::Error, &format!("Scheduled action: {}", f.result.description()));
+                    }
+                    step.restarts.push(Restart { conf: previous, cause: Cause::Rollback });
+                    step.changed = true;
+                },
+                None if self.verifying().is_none() => {
+                    target.report(LogLevel::Info, "Scheduled action: ODR-DabMux is healthy with the new configuration");
+                    step.changed = true;
+                },
+                None => (),
+            }
+        }
+
+        for scheduled in self.take_due(now) {
+            target.report(LogLevel::Info, &format!("Scheduled action: {}", scheduled.action.description()));
+            // The configuration of the instance once the previous restarts are carried out
+            let current = step.restarts.last().map_or(conf, |r| &r.conf);
+            if let Some(restart) = self.start(target, scheduled, current, others, now) {
+                step.restarts.push(restart);
+            }
+            step.changed = true;
+        }
+        step
+    }
+
+    fn start(&mut self, target: &Target, scheduled: ScheduledAction, current: &Config, others: &[&Config], now: DateTime<Utc>) -> Option<Restart> {
+        let health_timeout_s = match scheduled.action {
+            Action::Restart => return Some(Restart { conf: current.clone(), cause: Cause::Scheduled(scheduled) }),
+            Action::ApplyStaged { health_timeout_s } => health_timeout_s,
+        };
+
+        if self.verifying.is_some() {
+            let error = "another configuration change is being verified".to_owned();
+            self.finish(target, scheduled.id, scheduled.action, ActionResult::Failed { error });
+            return None;
+        }
+        let Some(staged) = self.staged().map(|s| s.conf.clone()) else {
+            let error = "no staged configuration".to_owned();
+            self.finish(target, scheduled.id, scheduled.action, ActionResult::Failed { error });
+            return None;
+        };
+        // Other instances may have taken its ports since it was staged. It stays staged, to be corrected.
+        let validation = staged.validate_with_others(others);
+        if !validation.is_ok() {
+            let issues : Vec<String> = validation.errors.iter().map(|i| i.message.clone()).collect();
+            let error = format!("the staged configuration is not valid: {}", issues.join(", "));
+            self.finish(target, scheduled.id, scheduled.action, ActionResult::Failed { error });
+            return None;
+        }
+        self.unstage();
+
+        self.verify(&scheduled, current.clone(), now, Duration::seconds(health_timeout_s as i64));
+        Some(Restart { conf: staged, cause: Cause::Apply })
+    }
+
+    /// Record the outcome of a restart returned by `run`. Returns the restart that goes back to
+    /// the previous configuration, if switching to the staged one failed.
+    pub fn restarted(&mut self, target: &Target, restart: Restart, result: anyhow::Result<()>) -> Option<Restart> {
+        match (restart.cause, result) {
+            (Cause::Scheduled(scheduled), Ok(())) => {
+                self.finish(target, scheduled.id, scheduled.action, ActionResult::Done);
+                None
+            },
+            (Cause::Scheduled(scheduled), Err(e)) => {
+                self.finish(target, scheduled.id, scheduled.action, ActionResult::Failed { error: format!("{:#}", e) });
+                None
+            },
+            (Cause::Apply, Ok(())) | (Cause::Rollback, Ok(())) => None,
+            (Cause::Apply, Err(e)) => {
+                let v = self.verifying.take()?;
+                self.finish(target, v.id, v.action, ActionResult::RolledBack { reason: format!("{:#}", e) });
+                Some(Restart { conf: v.previous, cause: Cause::Rollback })
+            },
+            (Cause::Rollback, Err(e)) => {
+                target.report(LogLevel::Error, &format!("Rollback failed: {:#}", e));
+                None
+            },
+        }
+    }
+
+    fn finish(&mut self, target: &Target, id: u64, action: Action, result: ActionResult) {
+        let level = if result == ActionResult::Done { LogLevel::Info } else { LogLevel::Error };
+        target.report(level, &format!("Scheduled action: {}", result.description()));
+        self.finished(id, action, result);
+    }
+
+    /// Record the result of an action
+    pub fn finished(&mut self, id: u64, action: Action, result: ActionResult) {
+        self.history.push_front(FinishedAction { id, action, finished: Utc::now(), result });
+        self.history.truncate(HISTORY_KEPT);
+    }
+
+    pub fn history(&self) -> &VecDeque<FinishedAction> {
+        &self.history
+    }
+}
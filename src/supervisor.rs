@@ -0,0 +1,399 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Runs odr-dabmux as a child process, restarts it with increasing delays when it exits on its
+//! own, and keeps a record of how it exited.
+
+use std::{collections::VecDeque, process::{ExitStatus, Stdio}, sync::Arc, time::Duration};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use tokio::{
+    process::{Child, Command},
+    sync::{mpsc, watch},
+    time::Instant,
+};
+
+use crate::muxlog::{LogLevel, LogSource, MuxLog};
+
+/// Delay before the first automatic restart, doubled after every crash
+const BACKOFF_MIN : Duration = Duration::from_secs(1);
+const BACKOFF_MAX : Duration = Duration::from_secs(60);
+/// A process that ran this long is considered stable, and the delay starts again from BACKOFF_MIN
+const STABLE_AFTER : Duration = Duration::from_secs(60);
+/// How long to wait for the process to exit after asking it to, before killing it
+const STOP_TIMEOUT : Duration = Duration::from_secs(5);
+/// Number of exits kept in the status
+const EXITS_KEPT : usize = 10;
+
+/// How to run the process
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupervisorSettings {
+    pub program : String,
+    pub args : Vec<String>,
+    pub auto_restart : bool,
+}
+
+impl SupervisorSettings {
+    pub fn command_line(&self) -> String {
+        std::iter::once(&self.program).chain(&self.args).cloned().collect::<Vec<_>>().join(" ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ProcessState {
+    Stopped,
+    Running { pid: u32, since: DateTime<Utc> },
+    /// Waiting before starting again after the process exited
+    Restarting { at: DateTime<Utc>, attempt: u32 },
+    /// The process could not be started, e.g. because the executable is missing
+    Failed { error: String },
+}
+
+impl ProcessState {
+    pub fn description(&self) -> String {
+        match self {
+            ProcessState::Stopped => "Stopped".to_owned(),
+            ProcessState::Running { pid, since } => format!("Running with pid {} since {}", pid, since.format("%Y-%m-%d %H:%M:%S UTC")),
+            ProcessState::Restarting { at, attempt } => format!("Exited, restarting at {} (attempt {})", at.format("%H:%M:%S UTC"), attempt),
+            ProcessState::Failed { error } => error.clone(),
+        }
+    }
+
+    pub fn css_class(&self) -> &'static str {
+        match self {
+            ProcessState::Stopped => "health-unreachable",
+            ProcessState::Running { .. } => "health-ok",
+            ProcessState::Restarting { .. } => "health-degraded",
+            ProcessState::Failed { .. } => "health-failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExitRecord {
+    pub time : DateTime<Utc>,
+    pub code : Option<i32>,
+    /// Signal that terminated the process
+    pub signal : Option<i32>,
+    pub ran_for_s : u64,
+    /// The process was stopped from the GUI
+    pub requested : bool,
+}
+
+impl ExitRecord {
+    pub fn description(&self) -> String {
+        match (self.code, self.signal) {
+            (Some(code), _) => format!("exit code {}", code),
+            (None, Some(signal)) => format!("signal {}", signal),
+            (None, None) => "unknown".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessStatus {
+    #[serde(flatten)]
+    pub state : ProcessState,
+    pub command_line : String,
+    /// Most recent first
+    pub exits : VecDeque<ExitRecord>,
+    /// Number of automatic restarts since the GUI started
+    pub restarts : u32,
+}
+
+#[derive(Debug)]
+enum Control {
+    Start,
+    Stop,
+    Restart,
+    Reconfigure(SupervisorSettings),
+}
+
+#[cfg(unix)]
+fn signal_of(status: &ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+fn signal_of(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+// Ask the process to exit cleanly, like systemd would
+#[cfg(unix)]
+fn terminate(child: &mut Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: sending a signal to a child we have not reaped yet
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM); }
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(child: &mut Child) {
+    let _ = child.start_kill();
+}
+
+enum Event {
+    Exited(std::io::Result<ExitStatus>),
+    RestartDue,
+    Control(Option<Control>),
+}
+
+struct Running {
+    child : Child,
+    started : Instant,
+}
+
+struct Task {
+    name : String,
+    settings : SupervisorSettings,
+    status_tx : watch::Sender<ProcessStatus>,
+    log : MuxLog,
+    running : Option<Running>,
+    restart_at : Option<Instant>,
+    /// Consecutive exits, used for the backoff
+    crashes : u32,
+}
+
+impl Task {
+    fn set_state(&self, state: ProcessState) {
+        self.status_tx.send_modify(|s| s.state = state);
+    }
+
+    fn start(&mut self) {
+        self.restart_at = None;
+        let spawned = Command::new(&self.settings.program)
+            .args(&self.settings.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+
+        match spawned {
+            Ok(mut child) => {
+                if let Some(stdout) = child.stdout.take() {
+                    tokio::spawn(self.log.clone().capture(LogSource::Stdout, stdout));
+                }
+                if let Some(stderr) = child.stderr.take() {
+                    tokio::spawn(self.log.clone().capture(LogSource::Stderr, stderr));
+                }
+
+                let pid = child.id().unwrap_or_default();
+                info!("{}: started {} with pid {}", self.name, self.settings.command_line(), pid);
+                self.log.push_level(LogSource::Supervisor, LogLevel::Info,
+                    &format!("Started {} with pid {}", self.settings.command_line(), pid));
+                self.running = Some(Running { child, started: Instant::now() });
+                self.set_state(ProcessState::Running { pid, since: Utc::now() });
+            },
+            Err(e) => {
+                error!("{}: cannot start {}: {}", self.name, self.settings.program, e);
+                self.log.push_level(LogSource::Supervisor, LogLevel::Error,
+                    &format!("Cannot start {}: {}", self.settings.program, e));
+                self.set_state(ProcessState::Failed { error: format!("Cannot start {}: {}", self.settings.program, e) });
+            },
+        }
+    }
+
+    fn record_exit(&mut self, status: std::io::Result<ExitStatus>, started: Instant, requested: bool) {
+        let (code, signal) = match &status {
+            Ok(status) => (status.code(), signal_of(status)),
+            Err(_) => (None, None),
+        };
+        let record = ExitRecord {
+            time: Utc::now(),
+            code,
+            signal,
+            ran_for_s: started.elapsed().as_secs(),
+            requested,
+        };
+        let message = format!("Process exited with {} after {} s", record.description(), record.ran_for_s);
+        if requested {
+            self.log.push_level(LogSource::Supervisor, LogLevel::Info, &message);
+        }
+        else {
+            warn!("{}: {}", self.name, message);
+            self.log.push_level(LogSource::Supervisor, LogLevel::Warn, &message);
+        }
+        self.status_tx.send_modify(|s| {
+            s.exits.push_front(record);
+            s.exits.truncate(EXITS_KEPT);
+        });
+    }
+
+    async fn stop(&mut self) {
+        self.restart_at = None;
+        self.crashes = 0;
+        if let Some(mut r) = self.running.take() {
+            terminate(&mut r.child);
+            let status = match tokio::time::timeout(STOP_TIMEOUT, r.child.wait()).await {
+                Ok(status) => status,
+                Err(_) => {
+                    warn!("{}: process did not exit within {} s, killing it", self.name, STOP_TIMEOUT.as_secs());
+                    let _ = r.child.kill().await;
+                    r.child.wait().await
+                },
+            };
+            self.record_exit(status, r.started, true);
+        }
+        self.set_state(ProcessState::Stopped);
+    }
+
+    // The process exited without being asked to
+    fn exited(&mut self, status: std::io::Result<ExitStatus>) {
+        let Some(r) = self.running.take() else { return };
+        let ran_for = r.started.elapsed();
+        self.record_exit(status, r.started, false);
+
+        if !self.settings.auto_restart {
+            self.set_state(ProcessState::Stopped);
+            return;
+        }
+
+        if ran_for >= STABLE_AFTER {
+            self.crashes = 0;
+        }
+        let delay = BACKOFF_MIN.saturating_mul(1 << self.crashes.min(16)).min(BACKOFF_MAX);
+        self.crashes += 1;
+
+        self.restart_at = Some(Instant::now() + delay);
+        self.status_tx.send_modify(|s| s.restarts += 1);
+        self.set_state(ProcessState::Restarting {
+            at: Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default(),
+            attempt: self.crashes,
+        });
+    }
+
+    async fn run(mut self, mut control_rx: mpsc::UnboundedReceiver<Control>) {
+        loop {
+            let event = {
+                let wait_exit = async {
+                    match self.running.as_mut() {
+                        Some(r) => r.child.wait().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let restart = async {
+                    match self.restart_at {
+                        Some(at) => tokio::time::sleep_until(at).await,
+                        None => std::future::pending().await,
+                    }
+                };
+
+                tokio::select! {
+                    status = wait_exit => Event::Exited(status),
+                    _ = restart => Event::RestartDue,
+                    control = control_rx.recv() => Event::Control(control),
+                }
+            };
+
+            match event {
+                Event::Exited(status) => self.exited(status),
+                Event::RestartDue => self.start(),
+                Event::Control(Some(Control::Start)) => {
+                    if self.running.is_none() {
+                        self.crashes = 0;
+                        self.start();
+                    }
+                },
+                Event::Control(Some(Control::Stop)) => self.stop().await,
+                Event::Control(Some(Control::Restart)) => {
+                    self.stop().await;
+                    self.start();
+                },
+                Event::Control(Some(Control::Reconfigure(settings))) => {
+                    self.status_tx.send_modify(|s| s.command_line = settings.command_line());
+                    self.settings = settings;
+                },
+                // The supervisor was dropped, the child is killed with the task
+                Event::Control(None) => return,
+            }
+        }
+    }
+}
+
+// Aborts the task, which kills the process, when the last clone of the supervisor is dropped
+struct TaskGuard(tokio::task::JoinHandle<()>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Supervises one process, e.g. odr-dabmux. Clones control the same process, which is killed
+/// when the last one is dropped.
+#[derive(Clone)]
+pub struct Supervisor {
+    control_tx : mpsc::UnboundedSender<Control>,
+    status_rx : watch::Receiver<ProcessStatus>,
+    _task : Arc<TaskGuard>,
+}
+
+impl Supervisor {
+    /// Start supervising, without starting the process. Its output goes to the log.
+    pub fn spawn(name: &str, settings: SupervisorSettings, log: MuxLog) -> Self {
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+        let (status_tx, status_rx) = watch::channel(ProcessStatus {
+            state: ProcessState::Stopped,
+            command_line: settings.command_line(),
+            exits: VecDeque::new(),
+            restarts: 0,
+        });
+
+        let task = Task {
+            name: name.to_owned(),
+            settings,
+            status_tx,
+            log,
+            running: None,
+            restart_at: None,
+            crashes: 0,
+        };
+        let task = tokio::spawn(task.run(control_rx));
+        Self { control_tx, status_rx, _task: Arc::new(TaskGuard(task)) }
+    }
+
+    fn send(&self, control: Control) {
+        // Only fails if the task panicked
+        let _ = self.control_tx.send(control);
+    }
+
+    pub fn start(&self) {
+        self.send(Control::Start);
+    }
+
+    pub fn stop(&self) {
+        self.send(Control::Stop);
+    }
+
+    pub fn restart(&self) {
+        self.send(Control::Restart);
+    }
+
+    /// Use other settings from the next start on, without restarting the process
+    pub fn reconfigure(&self, settings: SupervisorSettings) {
+        self.send(Control::Reconfigure(settings));
+    }
+
+    pub fn status(&self) -> ProcessStatus {
+        self.status_rx.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ProcessStatus> {
+        self.status_rx.clone()
+    }
+}
@@ -11,22 +11,30 @@
  *
  * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
  */
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 use askama::Template;
 use axum::{
     Json,
     Router,
-    extract::State,
-    http::StatusCode,
-    routing::{get, post},
+    extract::{Path, State, rejection::JsonRejection, ws::{Message, WebSocket, WebSocketUpgrade}},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get, post},
 };
-use log::info;
-use serde::Deserialize;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, watch};
 
 use tower_serve_static::{ServeDir};
 
 use crate::config;
-use crate::SharedState;
+use crate::dabmux::{Health, ParamKind, RcBatchResult, RcChange};
+use crate::encoder::EncoderStatus;
+use crate::muxlog::{self, LogLine};
+use crate::poller::Snapshot;
+use crate::scheduler::{Action, FinishedAction, ScheduledAction};
+use crate::supervisor::ProcessStatus;
+use crate::{Instance, SharedState};
 
 use include_dir::{include_dir, Dir};
 
@@ -34,10 +42,40 @@ static STATIC_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/static");
 
 pub async fn serve(port: u16, shared_state: SharedState) {
     let app = Router::new()
-        .route("/", get(dashboard))
-        .route("/settings", get(show_settings))
-        .route("/api/settings", post(post_settings))
-        .route("/api/set_rc", post(post_rc))
+        .route("/", get(overview))
+        .route("/instance/:name", get(dashboard))
+        .route("/instance/:name/settings", get(show_settings))
+        .route("/instance/:name/service/:uid", get(show_service))
+        .route("/instance/:name/log", get(show_log))
+        .route("/instance/:name/service/:uid/log", get(show_encoder_log))
+        .route("/api/instances", post(post_instance))
+        .route("/api/instance/:name", delete(delete_instance))
+        .route("/api/instance/:name/settings", post(post_settings))
+        .route("/api/instance/:name/apply_live", post(post_apply_live))
+        .route("/api/instance/:name/schedule", post(post_schedule))
+        .route("/api/instance/:name/schedule/:id", delete(delete_scheduled))
+        .route("/api/instance/:name/stage", post(post_stage).delete(delete_stage))
+        .route("/api/instance/:name/set_rc", post(post_rc))
+        .route("/api/instance/:name/set_rc_batch", post(post_rc_batch))
+        .route("/api/instance/:name/saved_rc", get(get_saved_rc))
+        .route("/api/instance/:name/rc/:module/:param", get(get_rc))
+        .route("/api/instance/:name/snapshot", get(get_snapshot))
+        .route("/api/instance/:name/live", get(live_snapshots))
+        .route("/api/instance/:name/process/:action", post(post_process))
+        .route("/api/instance/:name/log/live", get(live_log))
+        .route("/api/instance/:name/log.txt", get(download_log))
+        .route("/api/instance/:name/service/:uid/encoder/:action", post(post_encoder))
+        .route("/api/instance/:name/service/:uid/log/live", get(live_encoder_log))
+        .route("/api/instance/:name/service/:uid/log.txt", get(download_encoder_log))
+        .route("/api/instance/:name/save_template", post(post_save_template))
+        .route("/api/instance/:name/dabmux.json", get(download_dabmux_json))
+        .route("/api/instance/:name/encoders.tar", get(download_encoders))
+        .route("/templates", get(show_templates))
+        .route("/api/templates/export", get(export_templates))
+        .route("/api/templates/import", post(import_templates))
+        .route("/api/templates/service", post(post_service_template))
+        .route("/api/templates/ensemble/:name", delete(delete_ensemble_template))
+        .route("/api/templates/service/:name", delete(delete_service_template))
         .nest_service("/static", ServeDir::new(&STATIC_DIR))
         /* For an example for timeouts and tracing, have a look at the git history */
         .with_state(shared_state);
@@ -50,16 +88,165 @@ pub async fn serve(port: u16, shared_state: SharedState) {
 
 #[derive(PartialEq)]
 enum ActivePage {
+    Overview,
     Dashboard,
     Settings,
+    Service,
+    Log,
+    Templates,
 }
 
 impl ActivePage {
     // Used by templates/head.html to include the correct js files in <head>
     fn styles(&self) -> Vec<&'static str> {
         match self {
+            ActivePage::Overview => vec!["overview.js", "main.js"],
             ActivePage::Dashboard => vec!["dashboard.js", "main.js"],
             ActivePage::Settings => vec!["settings.js", "main.js"],
+            ActivePage::Service => vec!["service.js", "main.js"],
+            ActivePage::Log => vec!["log.js", "main.js"],
+            ActivePage::Templates => vec!["templates.js", "main.js"],
+        }
+    }
+}
+
+// Used by templates/head.html to show the instance switcher
+struct Nav {
+    instances: Vec<String>,
+    current: Option<String>,
+}
+
+impl Nav {
+    fn is_current(&self, name: &str) -> bool {
+        self.current.as_deref() == Some(name)
+    }
+}
+
+fn instance_not_found(name: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("No instance named {}", name))
+}
+
+struct InstanceHealth {
+    name: String,
+    ensemble_label: String,
+    ensemble_id: String,
+    num_services: usize,
+    version: Option<String>,
+    health: Health,
+    message: String,
+}
+
+#[derive(Template)]
+#[template(path = "overview.html")]
+struct OverviewTemplate<'a> {
+    title: &'a str,
+    page: ActivePage,
+    nav: Nav,
+    instances: Vec<InstanceHealth>,
+    templates: Vec<config::templates::EnsembleTemplate>,
+}
+
+async fn overview(State(state): State<SharedState>) -> OverviewTemplate<'static> {
+    let st = state.lock().unwrap();
+
+    let instances = st.instances.iter()
+        .map(|inst| {
+            let snapshot = inst.poller.snapshot();
+            let (version, health, message) = match (&snapshot.stats.value, &snapshot.stats.error) {
+                (Some(stats), None) => (
+                    Some(stats.version.clone()),
+                    stats.health(),
+                    format!("{} inputs, updated {}", stats.input_stats.len(), snapshot.stats.age_str())),
+                (stats, Some(e)) => (
+                    stats.as_ref().map(|s| s.version.clone()),
+                    Health::Unreachable,
+                    e.clone()),
+                (None, None) => (None, Health::Unreachable, "Not polled yet".to_owned()),
+            };
+
+            InstanceHealth {
+                name: inst.conf.instance_name.clone(),
+                ensemble_label: inst.conf.ensemble_label.clone(),
+                ensemble_id: inst.conf.ensemble_id_hex(),
+                num_services: inst.conf.services.len(),
+                version,
+                health,
+                message,
+            }
+        })
+        .collect();
+
+    OverviewTemplate {
+        title: "Overview",
+        page: ActivePage::Overview,
+        nav: Nav { instances: st.instance_names(), current: None },
+        instances,
+        templates: st.templates.with_builtin().ensembles,
+    }
+}
+
+#[derive(Deserialize)]
+struct NewInstance {
+    pub name : String,
+    pub template : Option<String>,
+}
+
+async fn post_instance(
+    State(state): State<SharedState>,
+    Json(new_instance): Json<NewInstance>) -> (StatusCode, Json<config::Validation>) {
+
+    let mut st = state.lock().unwrap();
+
+    if st.instance(&new_instance.name).is_some() {
+        let mut v = config::Validation::default();
+        v.error("instance_name", "Another instance already has this name");
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(v));
+    }
+
+    let others = st.other_confs(&new_instance.name);
+    let conf = match new_instance.template {
+        Some(template_name) => match st.templates.with_builtin().ensemble(&template_name) {
+            Some(template) => template.instantiate(&new_instance.name, &others),
+            None => {
+                let mut v = config::Validation::default();
+                v.error("template", format!("No template named {}", template_name));
+                return (StatusCode::NOT_FOUND, Json(v));
+            }
+        },
+        None => config::Config::new_instance(&new_instance.name, &others),
+    };
+    let validation = conf.validate_with_others(&st.other_confs(&new_instance.name));
+    if !validation.is_ok() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(validation));
+    }
+
+    st.instances.push(Instance::new(conf, Default::default()));
+    store_state(&st, validation)
+}
+
+async fn delete_instance(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> (StatusCode, Json<config::Validation>) {
+
+    let mut st = state.lock().unwrap();
+
+    let num_instances = st.instances.len();
+    st.instances.retain(|i| i.conf.instance_name != name);
+    if st.instances.len() == num_instances {
+        let mut v = config::Validation::default();
+        v.error("", format!("No instance named {}", name));
+        return (StatusCode::NOT_FOUND, Json(v));
+    }
+
+    store_state(&st, config::Validation::default())
+}
+
+fn store_state(st: &crate::AppState, mut validation: config::Validation) -> (StatusCode, Json<config::Validation>) {
+    match st.store() {
+        Ok(()) => (StatusCode::OK, Json(validation)),
+        Err(e) => {
+            validation.error("", format!("Failed to write UI config: {}", e));
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(validation))
         }
     }
 }
@@ -69,42 +256,485 @@ impl ActivePage {
 struct DashboardTemplate<'a> {
     title: &'a str,
     page: ActivePage,
+    nav: Nav,
     conf: config::Config,
+    snapshot: Arc<Snapshot>,
+    process: ProcessStatus,
+    pending: config::changes::Changes,
+    scheduler: SchedulerStatus,
+}
+
+async fn dashboard(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> Result<DashboardTemplate<'static>, (StatusCode, String)> {
+
+    let st = state.lock().unwrap();
+    let inst = st.instance(&name).ok_or_else(|| instance_not_found(&name))?;
+
+    Ok(DashboardTemplate {
+        title: "Dashboard",
+        nav: Nav { instances: st.instance_names(), current: Some(name.clone()) },
+        conf: inst.conf.clone(),
+        page: ActivePage::Dashboard,
+        snapshot: inst.poller.snapshot(),
+        process: inst.supervisor.status(),
+        pending: inst.pending_changes(),
+        scheduler: SchedulerStatus::new(inst),
+    })
+}
+
+// The staged configuration, with what it changes in the saved one
+#[derive(Serialize)]
+struct StagedSummary {
+    staged_at: chrono::DateTime<chrono::Utc>,
+    changes: Vec<String>,
+}
+
+// A change of configuration being verified, without the configuration to restore
+#[derive(Serialize)]
+struct VerifyingSummary {
+    id: u64,
+    started: chrono::DateTime<chrono::Utc>,
+    deadline: chrono::DateTime<chrono::Utc>,
+}
+
+// What the scheduler of an instance plans, carries out and did
+#[derive(Serialize)]
+struct SchedulerStatus {
+    schedule: Vec<ScheduledAction>,
+    staged: Option<StagedSummary>,
+    verifying: Option<VerifyingSummary>,
+    history: Vec<FinishedAction>,
+}
+
+impl SchedulerStatus {
+    fn new(inst: &Instance) -> Self {
+        let staged = inst.schedule.staged().map(|staged| {
+            let differences = config::drift::differences(&staged.conf.dabmux_json(), &inst.conf.dabmux_json());
+            let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "absent".to_owned());
+            StagedSummary {
+                staged_at: staged.staged_at,
+                changes: differences.iter()
+                    .map(|d| format!("{}: {} → {}", d.path, value(&d.running), value(&d.saved)))
+                    .collect(),
+            }
+        });
+        Self {
+            schedule: inst.schedule.upcoming().to_vec(),
+            staged,
+            verifying: inst.schedule.verifying()
+                .map(|v| VerifyingSummary { id: v.id, started: v.started, deadline: v.deadline }),
+            history: inst.schedule.history().iter().cloned().collect(),
+        }
+    }
+}
+
+// What the dashboard needs to show a stats ident or RC module as the service it belongs to
+#[derive(Serialize)]
+struct ServiceSummary {
+    unique_id: String,
+    label: String,
+    sid: String,
+    bitrate: u32,
+    input_port: u16,
+    idents: [String; 3],
+    /// None if the GUI does not run the encoders of the service
+    encoder: Option<EncoderStatus>,
+}
+
+impl ServiceSummary {
+    fn new(s: &config::Service, inst: &Instance) -> Self {
+        Self {
+            unique_id: s.unique_id.clone(),
+            label: s.label.clone(),
+            sid: s.sid_hex(),
+            bitrate: s.bitrate,
+            input_port: s.input_port,
+            idents: [s.rc_module(), s.subchannel_name(), s.component_name()],
+            encoder: inst.encoders.get(&s.unique_id).map(|enc| enc.status()),
+        }
+    }
+}
+
+// A snapshot, with the differences between the running and the saved configuration
+#[derive(Serialize)]
+struct LiveUpdate<'a> {
+    #[serde(flatten)]
+    snapshot: &'a Snapshot,
+    drift: Option<config::drift::Drift>,
+    services: Vec<ServiceSummary>,
+    process: ProcessStatus,
+    pending: config::changes::Changes,
+    #[serde(flatten)]
+    scheduler: SchedulerStatus,
+}
+
+fn live_update_json(state: &SharedState, name: &str, snapshot: &Snapshot) -> Result<String, (StatusCode, String)> {
+    let (conf, services, process, pending, scheduler) = {
+        let st = state.lock().unwrap();
+        let inst = st.instance(name).ok_or_else(|| instance_not_found(name))?;
+        let services = inst.conf.services.iter().map(|s| ServiceSummary::new(s, inst)).collect();
+        (inst.conf.clone(), services, inst.supervisor.status(), inst.pending_changes(), SchedulerStatus::new(inst))
+    };
+
+    let update = LiveUpdate {
+        snapshot,
+        drift: snapshot.running_config.value.as_ref().map(|running| conf.drift(running, snapshot.config_file.as_ref())),
+        services,
+        process,
+        pending,
+        scheduler,
+    };
+
+    serde_json::to_string(&update)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+#[derive(Template)]
+#[template(path = "service.html")]
+struct ServicePageTemplate<'a> {
+    title: &'a str,
+    page: ActivePage,
+    nav: Nav,
+    service: config::Service,
+    ecc: String,
+    input_stat: Option<crate::dabmux::InputStat>,
     params: Vec<crate::dabmux::Param>,
-    params_errors: Option<String>,
-    stats: Option<crate::dabmux::Stats>,
-    stats_errors: Option<String>,
+    encoder: Option<EncoderStatus>,
 }
 
-async fn dashboard(State(state): State<SharedState>) -> DashboardTemplate<'static> {
-    let (conf, params_result, stats_result) = {
-        let mut st = state.lock().unwrap();
+// Everything about one service: its configuration, its input statistics and its RC parameters
+async fn show_service(
+    State(state): State<SharedState>,
+    Path((name, uid)): Path<(String, String)>) -> Result<ServicePageTemplate<'static>, (StatusCode, String)> {
+
+    let st = state.lock().unwrap();
+    let inst = st.instance(&name).ok_or_else(|| instance_not_found(&name))?;
+    let service = inst.conf.service(&uid)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("No service {} in instance {}", uid, name)))?
+        .clone();
 
-        let params_result = st.dabmux.get_rc_parameters();
-        let stats_result = st.dabmux.get_stats();
-        info!("STATS: {:?}", stats_result);
+    let snapshot = inst.poller.snapshot();
+    let input_stat = snapshot.stats.value.as_ref()
+        .and_then(|s| s.input_stats.iter().find(|(ident, _)| *ident == service.subchannel_name()))
+        .map(|(_, is)| is.clone());
+    let params = snapshot.params.value.iter()
+        .flatten()
+        .filter(|p| service.has_ident(&p.module))
+        .cloned()
+        .collect();
+
+    Ok(ServicePageTemplate {
+        title: "Service",
+        page: ActivePage::Service,
+        nav: Nav { instances: st.instance_names(), current: Some(name) },
+        ecc: format!("{:02X}", service.effective_ecc(inst.conf.ensemble_ecc)),
+        encoder: inst.encoders.get(&service.unique_id).map(|enc| enc.status()),
+        service,
+        input_stat,
+        params,
+    })
+}
 
-        (st.conf.clone(), params_result, stats_result)
+async fn get_snapshot(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+
+    let snapshot = state.lock().unwrap().instance(&name).ok_or_else(|| instance_not_found(&name))?.poller.snapshot();
+    let json = live_update_json(&state, &name, &snapshot)?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], json))
+}
+
+// Streams every new snapshot as JSON, starting with the current one
+async fn live_snapshots(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    ws: WebSocketUpgrade) -> Result<impl IntoResponse, (StatusCode, String)> {
+
+    let (rx, process_rx) = {
+        let st = state.lock().unwrap();
+        let inst = st.instance(&name).ok_or_else(|| instance_not_found(&name))?;
+        (inst.poller.subscribe(), inst.supervisor.subscribe())
     };
+    Ok(ws.on_upgrade(move |socket| send_snapshots(socket, rx, process_rx, state, name)))
+}
+
+async fn send_snapshots(
+    mut socket: WebSocket,
+    mut rx: watch::Receiver<Arc<Snapshot>>,
+    mut process_rx: watch::Receiver<ProcessStatus>,
+    state: SharedState,
+    name: String) {
+    'outer: loop {
+        let snapshot = rx.borrow_and_update().clone();
+        process_rx.mark_unchanged();
+        let json = match live_update_json(&state, &name, &snapshot) {
+            Ok(json) => json,
+            Err((_, e)) => {
+                debug!("Cannot send snapshot of {}: {}", name, e);
+                break 'outer;
+            }
+        };
+
+        if socket.send(Message::Text(json)).await.is_err() {
+            break 'outer;
+        }
+
+        // Wait for the next snapshot, ignoring what the client sends until it closes
+        loop {
+            tokio::select! {
+                changed = rx.changed() => {
+                    // The instance was deleted or reconfigured, the client reconnects to the new poller
+                    if changed.is_err() {
+                        break 'outer;
+                    }
+                    break;
+                },
+                changed = process_rx.changed() => {
+                    if changed.is_err() {
+                        break 'outer;
+                    }
+                    break;
+                },
+                msg = socket.recv() => {
+                    match msg {
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break 'outer,
+                        _ => (),
+                    }
+                },
+            }
+        }
+    }
+
+    let _ = socket.close().await;
+}
 
-    let (params, params_errors) = match params_result {
-        Ok(v) => (v, None),
-        Err(e) => (Vec::new(), Some(format!("{}", e))),
+// Start, stop or restart odr-dabmux. Starting writes the generated configuration first.
+async fn post_process(
+    State(state): State<SharedState>,
+    Path((name, action)): Path<(String, String)>) -> (StatusCode, String) {
+
+    let st = state.lock().unwrap();
+    let Some(inst) = st.instance(&name) else {
+        return instance_not_found(&name);
     };
 
-    let (stats, stats_errors) = match stats_result {
-        Ok(v) => (Some(v), None),
-        Err(e) => (None, Some(format!("{}", e))),
+    let result = match action.as_str() {
+        "start" => inst.start_process(false),
+        "restart" => inst.start_process(true),
+        "stop" => {
+            inst.supervisor.stop();
+            Ok(())
+        },
+        _ => return (StatusCode::NOT_FOUND, format!("Unknown action {}", action)),
     };
 
-    DashboardTemplate {
-        title: "Dashboard",
-        conf,
-        page: ActivePage::Dashboard,
-        params,
-        params_errors,
-        stats,
-        stats_errors,
+    match result {
+        Ok(()) => (StatusCode::OK, "".to_owned()),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write odr-dabmux config: {:#}", e)),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "log.html")]
+struct LogTemplate<'a> {
+    title: &'a str,
+    page: ActivePage,
+    nav: Nav,
+    heading: String,
+    description: String,
+    /// Path of the log endpoints, relative to the instance API
+    api: String,
+    max_lines: usize,
+}
+
+async fn show_log(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> Result<LogTemplate<'static>, (StatusCode, String)> {
+
+    let st = state.lock().unwrap();
+    let conf = &st.instance(&name).ok_or_else(|| instance_not_found(&name))?.conf;
+
+    let mut description = "Output of ODR-DabMux when it is started from the Dashboard".to_owned();
+    if !conf.process.log_file.is_empty() {
+        description += &format!(", and the log file {}", conf.process.log_file);
+    }
+
+    Ok(LogTemplate {
+        title: "Log",
+        page: ActivePage::Log,
+        heading: "ODR-DabMux Log".to_owned(),
+        description,
+        api: "log".to_owned(),
+        max_lines: muxlog::LOG_LINES,
+        nav: Nav { instances: st.instance_names(), current: Some(name) },
+    })
+}
+
+fn encoder_not_found(name: &str, uid: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("The GUI does not run the encoders of service {} in instance {}", uid, name))
+}
+
+// The log of the mux, or of the encoders of a service
+fn find_log(state: &SharedState, name: &str, uid: Option<&str>) -> Result<muxlog::MuxLog, (StatusCode, String)> {
+    let st = state.lock().unwrap();
+    let inst = st.instance(name).ok_or_else(|| instance_not_found(name))?;
+    match uid {
+        None => Ok(inst.log.clone()),
+        Some(uid) => inst.encoders.get(uid)
+            .map(|enc| enc.log().clone())
+            .ok_or_else(|| encoder_not_found(name, uid)),
+    }
+}
+
+async fn show_encoder_log(
+    State(state): State<SharedState>,
+    Path((name, uid)): Path<(String, String)>) -> Result<LogTemplate<'static>, (StatusCode, String)> {
+
+    let st = state.lock().unwrap();
+    if !st.instance(&name).ok_or_else(|| instance_not_found(&name))?.encoders.contains_key(&uid) {
+        return Err(encoder_not_found(&name, &uid));
+    }
+
+    Ok(LogTemplate {
+        title: "Encoder Log",
+        page: ActivePage::Log,
+        heading: format!("Encoder Log: {}", uid),
+        description: format!("Output of ODR-AudioEnc and ODR-PadEnc for service {}", uid),
+        api: format!("service/{}/log", uid),
+        max_lines: muxlog::LOG_LINES,
+        nav: Nav { instances: st.instance_names(), current: Some(name) },
+    })
+}
+
+// Streams the log lines as JSON arrays, starting with all the kept lines
+async fn live_log(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    ws: WebSocketUpgrade) -> Result<impl IntoResponse, (StatusCode, String)> {
+
+    let (lines, rx) = find_log(&state, &name, None)?.subscribe();
+    Ok(ws.on_upgrade(move |socket| send_log(socket, lines, rx)))
+}
+
+async fn live_encoder_log(
+    State(state): State<SharedState>,
+    Path((name, uid)): Path<(String, String)>,
+    ws: WebSocketUpgrade) -> Result<impl IntoResponse, (StatusCode, String)> {
+
+    let (lines, rx) = find_log(&state, &name, Some(&uid))?.subscribe();
+    Ok(ws.on_upgrade(move |socket| send_log(socket, lines, rx)))
+}
+
+async fn send_log(mut socket: WebSocket, lines: Vec<LogLine>, mut rx: broadcast::Receiver<LogLine>) {
+    let mut pending = lines;
+    loop {
+        if !pending.is_empty() {
+            let json = serde_json::to_string(&pending).expect("log lines serialise");
+            if socket.send(Message::Text(json)).await.is_err() {
+                break;
+            }
+            pending.clear();
+        }
+
+        tokio::select! {
+            line = rx.recv() => {
+                match line {
+                    Ok(line) => {
+                        pending.push(line);
+                        // Send what else has arrived in the same message
+                        while let Ok(line) = rx.try_recv() {
+                            pending.push(line);
+                        }
+                    },
+                    // The viewer notices the gap from the sequence numbers
+                    Err(broadcast::error::RecvError::Lagged(_)) => (),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            },
+            msg = socket.recv() => {
+                match msg {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    _ => (),
+                }
+            },
+        }
+    }
+
+    let _ = socket.close().await;
+}
+
+fn log_download(log: &muxlog::MuxLog, filename: &str) -> impl IntoResponse {
+    let text : String = log.lines().iter().map(|l| format!("{}\n", l)).collect();
+    (
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8".to_owned()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        text,
+    )
+}
+
+async fn download_log(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+
+    let log = find_log(&state, &name, None)?;
+    Ok(log_download(&log, &format!("odr-dabmux-{}.log", name)))
+}
+
+async fn download_encoder_log(
+    State(state): State<SharedState>,
+    Path((name, uid)): Path<(String, String)>) -> Result<impl IntoResponse, (StatusCode, String)> {
+
+    let log = find_log(&state, &name, Some(&uid))?;
+    Ok(log_download(&log, &format!("encoders-{}-{}.log", name, uid)))
+}
+
+// Start, stop or restart the encoders of a service
+async fn post_encoder(
+    State(state): State<SharedState>,
+    Path((name, uid, action)): Path<(String, String, String)>) -> (StatusCode, String) {
+
+    let st = state.lock().unwrap();
+    let Some(inst) = st.instance(&name) else {
+        return instance_not_found(&name);
+    };
+    let Some(enc) = inst.encoders.get(&uid) else {
+        return encoder_not_found(&name, &uid);
+    };
+
+    match action.as_str() {
+        "start" => enc.start(),
+        "restart" => enc.restart(),
+        "stop" => enc.stop(),
+        _ => return (StatusCode::NOT_FOUND, format!("Unknown action {}", action)),
+    }
+    (StatusCode::OK, "".to_owned())
+}
+
+async fn get_saved_rc(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> Result<Json<Vec<config::rc::SavedRcValue>>, (StatusCode, String)> {
+
+    let st = state.lock().unwrap();
+    let inst = st.instance(&name).ok_or_else(|| instance_not_found(&name))?;
+    Ok(Json(inst.conf.saved_rc_values()))
+}
+
+// Read one parameter directly from the mux, bypassing the cached snapshot
+async fn get_rc(
+    State(state): State<SharedState>,
+    Path((name, module, param)): Path<(String, String, String)>) -> (StatusCode, String) {
+
+    let dabmux = match state.lock().unwrap().instance(&name) {
+        Some(inst) => inst.dabmux.clone(),
+        None => return instance_not_found(&name),
+    };
+
+    match dabmux.get_rc_parameter(&module, &param).await {
+        Ok(value) => (StatusCode::OK, value),
+        Err(e) => (StatusCode::BAD_GATEWAY, e.to_string()),
     }
 }
 
@@ -113,53 +743,601 @@ struct SetRc {
     pub module : String,
     pub param : String,
     pub value : String,
+    /// Also save the value in the configuration of the instance
+    #[serde(default)]
+    pub persist : bool,
+}
+
+// The configuration of the instance with the RC changes saved in it, which must still be valid
+fn conf_with_rc_changes(st: &crate::AppState, name: &str, changes: &[(&str, &str, &str)]) -> Result<config::Config, (StatusCode, String)> {
+    let inst = st.instance(name).ok_or_else(|| instance_not_found(name))?;
+
+    let mut conf = inst.conf.clone();
+    for (module, param, value) in changes {
+        conf.set_rc_value(module, param, value)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    }
+
+    let validation = conf.validate_with_others(&st.other_confs(name));
+    if !validation.is_ok() {
+        let messages : Vec<_> = validation.errors.iter().map(|e| e.message.as_str()).collect();
+        return Err((StatusCode::BAD_REQUEST, format!("Cannot be saved: {}", messages.join(", "))));
+    }
+    Ok(conf)
+}
+
+// Check that the changes can be saved before any of them is sent to the mux
+fn check_rc_changes(state: &SharedState, name: &str, changes: &[(&str, &str, &str)]) -> Result<crate::dabmux::DabMuxHandle, (StatusCode, String)> {
+    let st = state.lock().unwrap();
+    conf_with_rc_changes(&st, name, changes)?;
+    Ok(st.instance(name).ok_or_else(|| instance_not_found(name))?.dabmux.clone())
+}
+
+// Save RC changes that were applied to the mux in the configuration of the instance,
+// so that they survive the next restart of the mux
+fn persist_rc_changes(state: &SharedState, name: &str, changes: &[(&str, &str, &str)]) -> Result<(), (StatusCode, String)> {
+    let mut st = state.lock().unwrap();
+    let conf = conf_with_rc_changes(&st, name, changes)?;
+    let inst = st.instance_mut(name).ok_or_else(|| instance_not_found(name))?;
+
+    // The mux is still the same, no need to reconnect
+    inst.saving();
+    inst.conf = conf.clone();
+    // Already set on the running mux
+    for (module, param, value) in changes {
+        inst.applied_live(module, param, value);
+    }
+
+    st.store()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write UI config: {}", e)))?;
+    conf.write_dabmux_json()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write odr-dabmux config: {}", e)))
 }
 
 async fn post_rc(
     State(state): State<SharedState>,
+    Path(name): Path<String>,
     Json(set_rc): Json<SetRc>) -> (StatusCode, String) {
 
-    let set_rc_result = {
-        let mut st = state.lock().unwrap();
-        st.dabmux.set_rc_parameter(&set_rc.module, &set_rc.param, &set_rc.value)
+    let dabmux = if set_rc.persist {
+        match check_rc_changes(&state, &name, &[(&set_rc.module, &set_rc.param, &set_rc.value)]) {
+            Ok(dabmux) => dabmux,
+            Err(e) => return e,
+        }
+    }
+    else {
+        match state.lock().unwrap().instance(&name) {
+            Some(inst) => inst.dabmux.clone(),
+            None => return instance_not_found(&name),
+        }
     };
 
+    // As shown on the dashboard, where a label without short label is a plain string
+    let kind = match state.lock().unwrap().instance(&name) {
+        Some(inst) => {
+            let params = inst.poller.snapshot().params.value.clone().unwrap_or_default();
+            ParamKind::reported(&params, &set_rc.module, &set_rc.param)
+        },
+        None => return instance_not_found(&name),
+    };
+    if let Err(e) = kind.check(&set_rc.value) {
+        return (StatusCode::BAD_REQUEST, format!("{}.{}: {}", set_rc.module, set_rc.param, e));
+    }
+
+    let set_rc_result = dabmux.set_rc_parameter(&set_rc.module, &set_rc.param, &set_rc.value).await;
+
+    if let Some(inst) = state.lock().unwrap().instance(&name) {
+        inst.poller.refresh();
+    }
+
     match set_rc_result {
+        Ok(()) if set_rc.persist => {
+            match persist_rc_changes(&state, &name, &[(&set_rc.module, &set_rc.param, &set_rc.value)]) {
+                Ok(()) => (StatusCode::OK, "".to_owned()),
+                Err((status, e)) => (status, format!("Applied, but not saved: {}", e)),
+            }
+        },
         Ok(()) => (StatusCode::OK, "".to_owned()),
         Err(e) => (StatusCode::BAD_REQUEST, e.to_string()),
     }
 }
 
+#[derive(Deserialize)]
+struct SetRcBatch {
+    pub changes : Vec<RcChange>,
+    #[serde(default)]
+    pub persist : bool,
+}
+
+async fn post_rc_batch(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(batch): Json<SetRcBatch>) -> Result<(StatusCode, Json<RcBatchResult>), (StatusCode, String)> {
+
+    let dabmux = if batch.persist {
+        let changes : Vec<_> = batch.changes.iter()
+            .map(|c| (c.module.as_str(), c.param.as_str(), c.value.as_str()))
+            .collect();
+        check_rc_changes(&state, &name, &changes)?
+    }
+    else {
+        state.lock().unwrap().instance(&name).ok_or_else(|| instance_not_found(&name))?.dabmux.clone()
+    };
+
+    let result = dabmux.apply_rc_batch(batch.changes.clone()).await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+
+    if let Some(inst) = state.lock().unwrap().instance(&name) {
+        inst.poller.refresh();
+    }
+
+    if result.ok && batch.persist {
+        let changes : Vec<_> = batch.changes.iter()
+            .map(|c| (c.module.as_str(), c.param.as_str(), c.value.as_str()))
+            .collect();
+        persist_rc_changes(&state, &name, &changes)
+            .map_err(|(status, e)| (status, format!("Applied, but not saved: {}", e)))?;
+    }
+
+    let status = if result.ok { StatusCode::OK } else { StatusCode::BAD_REQUEST };
+    Ok((status, Json(result)))
+}
+
 #[derive(Template)]
 #[template(path = "settings.html")]
 struct SettingsTemplate<'a> {
     title: &'a str,
     page: ActivePage,
+    nav: Nav,
     conf: config::Config,
+    countries: &'static [config::countries::Country],
+    service_templates: Vec<config::templates::ServiceTemplate>,
 }
 
-async fn show_settings(State(state): State<SharedState>) -> SettingsTemplate<'static> {
-    SettingsTemplate {
+async fn show_settings(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> Result<SettingsTemplate<'static>, (StatusCode, String)> {
+
+    let st = state.lock().unwrap();
+    let conf = st.instance(&name).ok_or_else(|| instance_not_found(&name))?.conf.clone();
+
+    Ok(SettingsTemplate {
         title: "Settings",
         page: ActivePage::Settings,
-        conf: state.lock().unwrap().conf.clone(),
+        nav: Nav { instances: st.instance_names(), current: Some(name) },
+        conf,
+        countries: config::countries::COUNTRIES,
+        service_templates: st.templates.with_builtin().services,
+    })
+}
+
+#[derive(Serialize)]
+struct SettingsResult {
+    #[serde(flatten)]
+    validation: config::Validation,
+    /// Result of applying the changes the remote control can make, if the mux was reachable
+    applied: Option<RcBatchResult>,
+    apply_error: Option<String>,
+    /// What the running mux still lacks
+    pending: config::changes::Changes,
+}
+
+fn settings_result(status: StatusCode, validation: config::Validation) -> (StatusCode, Json<SettingsResult>) {
+    (status, Json(SettingsResult { validation, applied: None, apply_error: None, pending: Default::default() }))
+}
+
+// Validate, store and write a new configuration. Returns the connection to the running mux if it is reachable,
+// or why changes cannot be applied to it.
+fn save_settings(state: &SharedState, name: &str, conf: config::Config)
+    -> Result<(config::Validation, Result<crate::dabmux::DabMuxHandle, String>), (StatusCode, config::Validation)> {
+
+    let mut st = state.lock().unwrap();
+
+    if st.instance(name).is_none() {
+        let mut v = config::Validation::default();
+        v.error("", format!("No instance named {}", name));
+        return Err((StatusCode::NOT_FOUND, v));
     }
+
+    let mut validation = conf.validate_with_others(&st.other_confs(name));
+    if !validation.is_ok() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, validation));
+    }
+
+    // Written before the instance uses the new configuration, which it keeps only if both writes succeed
+    if let Err(e) = st.store_with(name, &conf) {
+        validation.error("", format!("Failed to write UI config: {}", e));
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, validation));
+    }
+    if let Err(e) = conf.write_dabmux_json() {
+        validation.error("", format!("Failed to write odr-dabmux config: {}", e));
+        if let Err(e) = st.store() {
+            validation.error("", format!("Failed to restore UI config: {}", e));
+        }
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, validation));
+    }
+
+    let mut running_mux = Err(format!("No instance named {}", name));
+    if let Some(inst) = st.instance_mut(name) {
+        // The running mux keeps its ports until it restarts
+        let stats = &inst.poller.snapshot().stats;
+        running_mux = match (&stats.value, &stats.error) {
+            (_, Some(e)) => Err(format!("the mux does not answer: {}", e)),
+            (None, None) => Err("the mux was not polled yet, try again from the Dashboard".to_owned()),
+            (Some(_), None) => Ok(inst.dabmux.clone()),
+        };
+        // Reconnect to the mux in case the ports changed
+        inst.reconfigure(conf);
+    }
+    Ok((validation, running_mux))
+}
+
+// Set the values of the saved configuration that the running mux lacks and the remote control can change
+async fn apply_live_changes(state: &SharedState, name: &str, dabmux: crate::dabmux::DabMuxHandle) -> Result<Option<RcBatchResult>, String> {
+    let changes = state.lock().unwrap().instance(name)
+        .ok_or_else(|| format!("No instance named {}", name))?
+        .pending_changes().live;
+    if changes.is_empty() {
+        return Ok(None);
+    }
+
+    let result = dabmux.apply_rc_batch(changes.clone()).await.map_err(|e| e.to_string())?;
+    if let Some(inst) = state.lock().unwrap().instance_mut(name) {
+        if result.ok {
+            for c in &changes {
+                inst.applied_live(&c.module, &c.param, &c.value);
+            }
+        }
+        inst.poller.refresh();
+    }
+    Ok(Some(result))
 }
 
 async fn post_settings(
     State(state): State<SharedState>,
-    Json(conf): Json<config::Config>) -> (StatusCode, String) {
+    Path(name): Path<String>,
+    conf: Result<Json<config::Config>, JsonRejection>) -> (StatusCode, Json<SettingsResult>) {
 
-    match conf.store() {
-        Ok(()) => {
-            state.lock().unwrap().conf.clone_from(&conf);
+    let conf = match conf {
+        Ok(Json(conf)) => conf,
+        Err(e) => {
+            let mut v = config::Validation::default();
+            v.error("", e.body_text());
+            return settings_result(e.status(), v);
+        }
+    };
 
-            match conf.write_dabmux_json() {
-                Ok(()) => (StatusCode::OK, "".to_owned()),
-                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Failed to write odr-dabmux config: {}", e.to_string()))
-            }
+    // The instance is renamed when the configuration changes its name
+    let new_name = conf.instance_name.clone();
+    let (validation, running_mux) = match save_settings(&state, &name, conf) {
+        Ok(saved) => saved,
+        Err((status, validation)) => return settings_result(status, validation),
+    };
+
+    let mut result = SettingsResult { validation, applied: None, apply_error: None, pending: Default::default() };
+    match running_mux {
+        Ok(dabmux) => match apply_live_changes(&state, &new_name, dabmux).await {
+            Ok(applied) => result.applied = applied,
+            Err(e) => result.apply_error = Some(e),
+        },
+        Err(reason) => result.apply_error = Some(reason),
+    }
+    if let Some(inst) = state.lock().unwrap().instance(&new_name) {
+        result.pending = inst.pending_changes();
+    }
+    // Only worth mentioning when there was something to apply
+    if result.applied.is_none() && result.pending.live.is_empty() {
+        result.apply_error = None;
+    }
+    (StatusCode::OK, Json(result))
+}
+
+// Apply the pending changes the remote control can make, e.g. after the mux was unreachable when they were saved
+async fn post_apply_live(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> Result<(StatusCode, Json<Option<RcBatchResult>>), (StatusCode, String)> {
+
+    let dabmux = state.lock().unwrap().instance(&name).ok_or_else(|| instance_not_found(&name))?.dabmux.clone();
+    let result = apply_live_changes(&state, &name, dabmux).await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+
+    let status = if result.as_ref().is_some_and(|r| !r.ok) { StatusCode::BAD_REQUEST } else { StatusCode::OK };
+    Ok((status, Json(result)))
+}
+
+#[derive(Deserialize)]
+struct NewScheduledAction {
+    at: chrono::DateTime<chrono::Utc>,
+    #[serde(flatten)]
+    action: Action,
+}
+
+async fn post_schedule(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(new): Json<NewScheduledAction>) -> Result<Json<u64>, (StatusCode, String)> {
+
+    if new.at <= chrono::Utc::now() {
+        return Err((StatusCode::BAD_REQUEST, "The time is in the past".to_owned()));
+    }
+    let mut st = state.lock().unwrap();
+    let inst = st.instance_mut(&name).ok_or_else(|| instance_not_found(&name))?;
+    if matches!(new.action, Action::ApplyStaged { .. }) {
+        if inst.schedule.staged().is_none() {
+            return Err((StatusCode::BAD_REQUEST, "No configuration is staged".to_owned()));
+        }
+        // The first one would leave nothing to apply to the others
+        if inst.schedule.upcoming().iter().any(|a| matches!(a.action, Action::ApplyStaged { .. })) {
+            return Err((StatusCode::CONFLICT, "Applying the staged configuration is already scheduled".to_owned()));
+        }
+    }
+    let id = inst.schedule.add(new.at, new.action);
+    st.store()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write UI config: {}", e)))?;
+    Ok(Json(id))
+}
+
+fn store_schedule(st: &crate::AppState) -> (StatusCode, String) {
+    match st.store() {
+        Ok(()) => (StatusCode::OK, "".to_owned()),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write UI config: {}", e)),
+    }
+}
+
+async fn delete_scheduled(
+    State(state): State<SharedState>,
+    Path((name, id)): Path<(String, u64)>) -> (StatusCode, String) {
+
+    let mut st = state.lock().unwrap();
+    let Some(inst) = st.instance_mut(&name) else {
+        return instance_not_found(&name);
+    };
+    if !inst.schedule.cancel(id) {
+        return (StatusCode::NOT_FOUND, format!("No scheduled action {}", id));
+    }
+    store_schedule(&st)
+}
+
+// Keep a configuration to apply with a scheduled action, instead of saving it now
+async fn post_stage(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    conf: Result<Json<config::Config>, JsonRejection>) -> (StatusCode, Json<config::Validation>) {
+
+    let mut v = config::Validation::default();
+    let conf = match conf {
+        Ok(Json(conf)) => conf,
+        Err(e) => {
+            v.error("", e.body_text());
+            return (e.status(), Json(v));
+        }
+    };
+
+    let mut st = state.lock().unwrap();
+    if st.instance(&name).is_none() {
+        v.error("", format!("No instance named {}", name));
+        return (StatusCode::NOT_FOUND, Json(v));
+    }
+
+    let mut v = conf.validate_with_others(&st.other_confs(&name));
+    if conf.instance_name != name {
+        v.error("instance_name", "A staged configuration cannot rename the instance");
+    }
+    if !v.is_ok() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(v));
+    }
+    if let Some(inst) = st.instance_mut(&name) {
+        inst.schedule.stage(conf);
+    }
+    store_state(&st, v)
+}
+
+async fn delete_stage(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> (StatusCode, String) {
+
+    let mut st = state.lock().unwrap();
+    let Some(inst) = st.instance_mut(&name) else {
+        return instance_not_found(&name);
+    };
+    // The scheduled action would have nothing to apply
+    if inst.schedule.upcoming().iter().any(|a| matches!(a.action, Action::ApplyStaged { .. })) {
+        return (StatusCode::CONFLICT, "Applying the staged configuration is scheduled, cancel it first".to_owned());
+    }
+    if !inst.schedule.unstage() {
+        return (StatusCode::NOT_FOUND, "No configuration is staged".to_owned());
+    }
+    store_schedule(&st)
+}
+
+async fn download_dabmux_json(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+
+    let conf = state.lock().unwrap().instance(&name).ok_or_else(|| instance_not_found(&name))?.conf.clone();
+    let json = serde_json::to_string_pretty(&conf.dabmux_json())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_owned()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"odr-dabmux-{}.json\"", name)),
+        ],
+        json,
+    ))
+}
+
+// A tar archive containing the mux JSON and the encoder command lines and systemd units
+fn encoders_bundle(conf: &config::Config) -> std::io::Result<Vec<u8>> {
+    let mut files = conf.encoder_files();
+    files.push(config::encoders::EncoderFile {
+        name: "odr-dabmux.json".to_owned(),
+        contents: serde_json::to_string_pretty(&conf.dabmux_json())?,
+    });
+
+    let mut builder = tar::Builder::new(Vec::new());
+    for f in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(f.contents.len() as u64);
+        header.set_mode(if f.name.ends_with(".sh") { 0o755 } else { 0o644 });
+        header.set_mtime(chrono::Utc::now().timestamp() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, format!("{}/{}", conf.instance_name, f.name), f.contents.as_bytes())?;
+    }
+    builder.into_inner()
+}
+
+async fn download_encoders(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+
+    let conf = state.lock().unwrap().instance(&name).ok_or_else(|| instance_not_found(&name))?.conf.clone();
+    let bundle = encoders_bundle(&conf)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_owned()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"encoders-{}.tar\"", name)),
+        ],
+        bundle,
+    ))
+}
+
+#[derive(Template)]
+#[template(path = "templates.html")]
+struct TemplatesTemplate<'a> {
+    title: &'a str,
+    page: ActivePage,
+    nav: Nav,
+    templates: config::templates::Templates,
+}
+
+impl TemplatesTemplate<'_> {
+    fn is_builtin_ensemble(&self, name: &str) -> bool {
+        config::templates::Templates::is_builtin_ensemble(name)
+    }
+
+    fn is_builtin_service(&self, name: &str) -> bool {
+        config::templates::Templates::is_builtin_service(name)
+    }
+}
+
+async fn show_templates(State(state): State<SharedState>) -> TemplatesTemplate<'static> {
+    let st = state.lock().unwrap();
+    TemplatesTemplate {
+        title: "Templates",
+        page: ActivePage::Templates,
+        nav: Nav { instances: st.instance_names(), current: None },
+        templates: st.templates.with_builtin(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SaveTemplate {
+    pub name : String,
+    pub description : String,
+}
+
+fn check_template_name(v: &mut config::Validation, name: &str, is_builtin: bool) {
+    if name.trim().is_empty() {
+        v.error("template_name", "Template name must not be empty");
+    }
+    else if is_builtin {
+        v.error("template_name", "A built-in template already has this name");
+    }
+}
+
+async fn post_save_template(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(save): Json<SaveTemplate>) -> (StatusCode, Json<config::Validation>) {
+
+    let mut st = state.lock().unwrap();
+
+    let mut v = config::Validation::default();
+    check_template_name(&mut v, &save.name, config::templates::Templates::is_builtin_ensemble(&save.name));
+    if !v.is_ok() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(v));
+    }
+
+    let template = match st.instance(&name) {
+        Some(inst) => config::templates::EnsembleTemplate::from_config(&save.name, &save.description, &inst.conf),
+        None => {
+            v.error("", format!("No instance named {}", name));
+            return (StatusCode::NOT_FOUND, Json(v));
+        }
+    };
+
+    st.templates.merge(config::templates::Templates { ensembles: vec![template], services: vec![] });
+    store_state(&st, v)
+}
+
+async fn post_service_template(
+    State(state): State<SharedState>,
+    Json(template): Json<config::templates::ServiceTemplate>) -> (StatusCode, Json<config::Validation>) {
+
+    let mut st = state.lock().unwrap();
+
+    let mut v = config::Validation::default();
+    check_template_name(&mut v, &template.name, config::templates::Templates::is_builtin_service(&template.name));
+    if !v.is_ok() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(v));
+    }
+
+    st.templates.merge(config::templates::Templates { ensembles: vec![], services: vec![template] });
+    store_state(&st, v)
+}
+
+async fn delete_ensemble_template(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> (StatusCode, Json<config::Validation>) {
+
+    let mut st = state.lock().unwrap();
+    st.templates.ensembles.retain(|t| t.name != name);
+    store_state(&st, config::Validation::default())
+}
+
+async fn delete_service_template(
+    State(state): State<SharedState>,
+    Path(name): Path<String>) -> (StatusCode, Json<config::Validation>) {
+
+    let mut st = state.lock().unwrap();
+    st.templates.services.retain(|t| t.name != name);
+    store_state(&st, config::Validation::default())
+}
+
+async fn export_templates(State(state): State<SharedState>) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let st = state.lock().unwrap();
+    let toml = toml::to_string_pretty(&st.templates)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/toml"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"odr-dabmux-gui-templates.toml\""),
+        ],
+        toml,
+    ))
+}
+
+async fn import_templates(
+    State(state): State<SharedState>,
+    body: String) -> (StatusCode, Json<config::Validation>) {
+
+    let mut v = config::Validation::default();
+
+    let templates : config::templates::Templates = match toml::from_str(&body) {
+        Ok(t) => t,
+        Err(e) => {
+            v.error("", format!("Invalid templates file: {}", e));
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(v));
         }
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write UI config: {}", e.to_string()))
+    };
+
+    let mut st = state.lock().unwrap();
+    for name in st.templates.merge(templates) {
+        v.warning("", format!("Template {} has the name of a built-in template and was not imported", name));
     }
+    store_state(&st, v)
 }
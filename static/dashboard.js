@@ -1,5 +1,625 @@
-async function btn_dash_update(element_clicked, module, param) {
-    let value = element_clicked.parentElement.children[0].value;
-    let data = {'module': module, 'param': param, 'value': value};
-    await post('/api/set_rc', data);
+// Last snapshot received over the WebSocket, used to highlight what changed
+let previous_snapshot = null;
+
+function age_str(updated) {
+    if (updated === null) {
+        return "never";
+    }
+    const age = Math.max(0, Math.round((Date.now() - Date.parse(updated)) / 1000));
+    return `${age} s ago`;
 }
+
+function update_ages() {
+    if (previous_snapshot !== null) {
+        document.getElementById('stats_age').textContent = age_str(previous_snapshot.stats.updated);
+        document.getElementById('params_age').textContent = age_str(previous_snapshot.params.updated);
+    }
+}
+
+function flash(element, css_class) {
+    element.classList.remove(css_class);
+    // Force a reflow so that the animation restarts
+    void element.offsetWidth;
+    element.classList.add(css_class);
+}
+
+function fill_bar(is, largest_fill) {
+    const bar = document.createElement('div');
+    bar.className = 'fill-bar';
+    if (is.max_fill === null || is.min_fill === null) {
+        return bar;
+    }
+    bar.title = `min ${is.min_fill}, max ${is.max_fill}`;
+    const max = document.createElement('div');
+    max.className = 'fill-bar-max';
+    max.style.width = `${largest_fill > 0 ? 100 * is.max_fill / largest_fill : 0}%`;
+    const min = document.createElement('div');
+    min.className = 'fill-bar-min';
+    min.style.width = `${is.max_fill > 0 ? 100 * is.min_fill / is.max_fill : 0}%`;
+    max.appendChild(min);
+    bar.appendChild(max);
+    return bar;
+}
+
+function render_stats(cached, previous) {
+    document.getElementById('stats_error').textContent = cached.error === null ? "" : `Error!: ${cached.error}`;
+
+    const stats = cached.value;
+    if (stats === null) {
+        return;
+    }
+    document.getElementById('stats_version').textContent = stats.version;
+    document.getElementById('stats_uptime').textContent = stats.uptime === null ? "N/A" : `${stats.uptime} s`;
+
+    const other = Object.keys(stats.other_values).length > 0;
+    document.getElementById('stats_other').hidden = !other;
+    document.getElementById('stats_other_values').textContent = other ? JSON.stringify(stats.other_values, null, 2) : "";
+
+    const previous_inputs = new Map(previous?.value?.input_stats ?? []);
+    // Bars are relative to the input with the largest buffer
+    const largest_fill = Math.max(0, ...stats.input_stats.map(([_, is]) => is.max_fill ?? 0));
+
+    const body = document.getElementById('stats_body');
+    body.replaceChildren();
+    for (const [ident, is] of stats.input_stats) {
+        const prev = previous_inputs.get(ident);
+        const row = document.createElement('tr');
+        row.dataset.ident = ident;
+        cell(row, ident);
+        cell(row, "");
+        cell(row, "").appendChild(fill_bar(is, largest_fill));
+        cell(row, optional(is.max_fill));
+        cell(row, optional(is.min_fill));
+        const under = cell(row, optional(is.num_underruns));
+        const over = cell(row, optional(is.num_overruns));
+        cell(row, level(is.peak_left));
+        cell(row, level(is.peak_right));
+        cell(row, level(is.peak_left_slow));
+        cell(row, level(is.peak_right_slow));
+        const state = cell(row, optional(is.state));
+        state.classList.add(state_class(is.state));
+        cell(row, optional(is.version));
+        cell(row, optional(is.uptime));
+        cell(row, optional(is.last_tist_offset));
+
+        // Fields specific to the type of input are flattened into the input stats
+        const known = ['max_fill', 'min_fill', 'num_underruns', 'num_overruns', 'peak_left', 'peak_right',
+            'peak_left_slow', 'peak_right_slow', 'state', 'version', 'uptime', 'last_tist_offset'];
+        cell(row, Object.entries(is)
+            .filter(([k, _]) => !known.includes(k))
+            .map(([k, v]) => `${k}=${JSON.stringify(v)}`)
+            .join(" "));
+
+        if (prev !== undefined) {
+            if (is.num_underruns > prev.num_underruns) {
+                flash(under, 'changed-bad');
+            }
+            if (is.num_overruns > prev.num_overruns) {
+                flash(over, 'changed-bad');
+            }
+            if (is.state !== prev.state) {
+                flash(state, 'changed');
+            }
+        }
+        body.appendChild(row);
+    }
+}
+
+// Same checks as ParamKind::check on the server. Returns an error message, or null if the value is valid.
+function check_param_value(kind, value) {
+    switch (kind.type) {
+        case "bool":
+            return (value == "0" || value == "1") ? null : `${value} is not 0 or 1`;
+        case "int": {
+            if (!/^-?[0-9]+$/.test(value)) {
+                return `${value} is not an integer`;
+            }
+            const i = parseInt(value, 10);
+            return (i >= kind.min && i <= kind.max) ? null : `${i} is not between ${kind.min} and ${kind.max}`;
+        }
+        case "enum":
+            return kind.values.includes(value) ? null : `${value} is not one of ${kind.values.join(", ")}`;
+        case "label_pair": {
+            const [label, shortlabel] = split_label_pair(value);
+            if (label.length == 0 || shortlabel.length == 0) {
+                return "Label and short label must not be empty";
+            }
+            if ([...label].length > 16) {
+                return "Label is longer than 16 characters";
+            }
+            if ([...shortlabel].length > 8) {
+                return "Short label is longer than 8 characters";
+            }
+            let rest = label;
+            for (const c of shortlabel) {
+                const pos = rest.indexOf(c);
+                if (pos < 0) {
+                    return "Short label must be made of characters of the label, in the same order";
+                }
+                rest = rest.substring(pos + 1);
+            }
+            return null;
+        }
+        case "read_only":
+            return "Parameter is read-only";
+        default:
+            return null;
+    }
+}
+
+function split_label_pair(value) {
+    const comma = value.indexOf(',');
+    return comma < 0 ? [value, ""] : [value.substring(0, comma), value.substring(comma + 1)];
+}
+
+function text_input(value) {
+    const input = document.createElement('input');
+    input.className = 'textinput';
+    input.type = 'text';
+    input.value = value;
+    return input;
+}
+
+// The widget for a parameter, with functions to read and replace its value
+function param_editor(p) {
+    const element = document.createElement('span');
+
+    switch (p.kind.type) {
+        case "bool": {
+            const input = document.createElement('input');
+            input.type = 'checkbox';
+            input.checked = p.value == "1";
+            element.appendChild(input);
+            return { element, get: () => input.checked ? "1" : "0", set: v => input.checked = v == "1" };
+        }
+        case "int": {
+            const input = text_input(p.value);
+            input.type = 'number';
+            input.min = p.kind.min;
+            input.max = p.kind.max;
+            element.appendChild(input);
+            return { element, get: () => input.value, set: v => input.value = v };
+        }
+        case "enum": {
+            const select = document.createElement('select');
+            for (const value of p.kind.values) {
+                select.add(new Option(value, value));
+            }
+            // Show the current value even if it is not one we know
+            if (!p.kind.values.includes(p.value)) {
+                select.add(new Option(p.value, p.value));
+            }
+            select.value = p.value;
+            element.appendChild(select);
+            return { element, get: () => select.value, set: v => select.value = v };
+        }
+        case "label_pair": {
+            const [label, shortlabel] = split_label_pair(p.value);
+            const label_input = text_input(label);
+            label_input.maxLength = 16;
+            label_input.title = "Label";
+            const shortlabel_input = text_input(shortlabel);
+            shortlabel_input.maxLength = 8;
+            shortlabel_input.size = 8;
+            shortlabel_input.title = "Short label";
+            element.append(label_input, shortlabel_input);
+            return {
+                element,
+                get: () => `${label_input.value},${shortlabel_input.value}`,
+                set: v => [label_input.value, shortlabel_input.value] = split_label_pair(v),
+            };
+        }
+        case "read_only":
+            element.textContent = p.value;
+            return { element, get: () => p.value, set: v => element.textContent = v };
+        default: {
+            const input = text_input(p.value);
+            element.appendChild(input);
+            return { element, get: () => input.value, set: v => input.value = v };
+        }
+    }
+}
+
+async function update_param(row, p) {
+    const value = row.editor.get();
+    const message = row.querySelector('.param-message');
+    const error = check_param_value(p.kind, value);
+    message.textContent = error ?? "";
+    row.editor.element.classList.toggle('input-error', error !== null);
+    if (error === null) {
+        const persist = document.getElementById('rc_persist').checked;
+        const ok = await post(instance_api('set_rc'), {'module': p.module, 'param': p.param, 'value': value, 'persist': persist});
+        if (ok && persist) {
+            await load_saved_values();
+        }
+    }
+}
+
+// Values of the RC parameters in the saved configuration, keyed by module.param
+let saved_values = new Map();
+
+async function load_saved_values() {
+    const response = await fetch(instance_api('saved_rc'));
+    if (response.ok) {
+        const saved = await response.json();
+        saved_values = new Map(saved.map(v => [`${v.module}.${v.param}`, v.value]));
+        mark_saved_differences();
+    }
+}
+
+// Highlight the live values that a restart of the mux would revert
+function mark_saved_differences() {
+    for (const row of document.getElementById('params_body').rows) {
+        const saved = saved_values.get(`${row.dataset.module}.${row.dataset.param}`);
+        const differs = saved !== undefined && row.dataset.value !== undefined && saved !== row.dataset.value;
+        row.cells[2].classList.toggle('differs-from-saved', differs);
+        row.cells[2].title = differs ? `Saved configuration: ${saved}` : "";
+    }
+}
+
+// Changes staged for a batch, keyed by module.param so that staging again replaces the value
+const pending_changes = new Map();
+
+function render_pending(results) {
+    const result_of = new Map((results ?? []).map(r => [`${r.module}.${r.param}`, r]));
+    const body = document.getElementById('pending_body');
+    body.replaceChildren();
+    for (const [key, c] of pending_changes) {
+        const row = document.createElement('tr');
+        cell(row, c.module);
+        cell(row, c.param);
+        cell(row, c.value);
+        const r = result_of.get(key);
+        const status = cell(row, r === undefined ? "" : r.status.replace('_', ' ') + (r.error ? `: ${r.error}` : ""));
+        if (r !== undefined) {
+            status.classList.add(r.status == "applied" ? 'health-ok' : 'health-failed');
+        }
+        body.appendChild(row);
+    }
+    document.getElementById('pending_section').hidden = pending_changes.size == 0;
+}
+
+function stage_param(row, p) {
+    const value = row.editor.get();
+    const message = row.querySelector('.param-message');
+    const error = check_param_value(p.kind, value);
+    message.textContent = error ?? "";
+    row.editor.element.classList.toggle('input-error', error !== null);
+    if (error === null) {
+        pending_changes.set(`${p.module}.${p.param}`, {'module': p.module, 'param': p.param, 'value': value});
+        render_pending();
+    }
+}
+
+async function btn_apply_pending() {
+    const response = await fetch(instance_api('set_rc_batch'), {
+        method: "POST",
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify({
+            'changes': Array.from(pending_changes.values()),
+            'persist': document.getElementById('rc_persist').checked,
+        }),
+    });
+
+    if (response.headers.get('Content-Type')?.startsWith('application/json')) {
+        const result = await response.json();
+        render_pending(result.results);
+        if (result.ok) {
+            await load_saved_values();
+            pending_changes.clear();
+            setTimeout(render_pending, 3000);
+        }
+    }
+    else {
+        alert(`Error Sending: ${response.statusText} ${await response.text()}`);
+    }
+}
+
+function btn_discard_pending() {
+    pending_changes.clear();
+    render_pending();
+}
+
+function param_row(p) {
+    const row = document.createElement('tr');
+    row.dataset.module = p.module;
+    row.dataset.param = p.param;
+    row.dataset.value = p.value;
+    cell(row, p.module);
+    const name = cell(row, p.param);
+    if (p.write_only) {
+        const tag = document.createElement('span');
+        tag.className = 'param-tag';
+        tag.textContent = 'write-only';
+        tag.title = 'The mux does not report the value of this parameter';
+        name.appendChild(tag);
+    }
+    if (p.description) {
+        const help = document.createElement('div');
+        help.className = 'param-help';
+        help.textContent = p.description;
+        name.appendChild(help);
+    }
+
+    row.editor = param_editor(p);
+    cell(row, "").appendChild(row.editor.element);
+
+    const td = cell(row, "");
+    if (p.kind.type != "read_only") {
+        const button = document.createElement('button');
+        button.className = 'btn';
+        button.type = 'button';
+        button.textContent = 'Update';
+        button.addEventListener('click', () => update_param(row, p));
+        td.appendChild(button);
+
+        const stage = document.createElement('button');
+        stage.className = 'btn';
+        stage.type = 'button';
+        stage.textContent = 'Stage';
+        stage.title = 'Add to the pending changes, to apply several changes together';
+        stage.addEventListener('click', () => stage_param(row, p));
+        td.appendChild(stage);
+    }
+    const message = document.createElement('span');
+    message.className = 'param-message input-error';
+    td.appendChild(message);
+    return row;
+}
+
+function render_params(cached) {
+    document.getElementById('params_error').textContent = cached.error === null ? "" : `Error!: ${cached.error}`;
+
+    const params = cached.value;
+    if (params === null) {
+        return;
+    }
+
+    const body = document.getElementById('params_body');
+    const rows = new Map(Array.from(body.rows, r => [`${r.dataset.module}.${r.dataset.param}`, r]));
+    const same_params = rows.size == params.length && params.every(p => rows.has(`${p.module}.${p.param}`));
+
+    if (!same_params) {
+        body.replaceChildren(...params.map(param_row));
+        mark_saved_differences();
+        return;
+    }
+
+    // Update in place, without touching a value the user is editing
+    for (const p of params) {
+        const row = rows.get(`${p.module}.${p.param}`);
+        if (!p.write_only && row.dataset.value !== p.value) {
+            row.dataset.value = p.value;
+            if (!row.contains(document.activeElement)) {
+                row.editor.set(p.value);
+            }
+            flash(row.cells[2], 'changed');
+        }
+    }
+    mark_saved_differences();
+}
+
+function render_drift(drift) {
+    const banner = document.getElementById('drift_banner');
+    banner.hidden = drift === null || drift.differences.length == 0;
+    if (banner.hidden) {
+        return;
+    }
+
+    document.getElementById('drift_restart').hidden = !drift.restart_needed;
+    document.getElementById('drift_file').hidden = !drift.file_differs;
+    const list = document.getElementById('drift_list');
+    list.replaceChildren(...drift.differences.map(d => {
+        const li = document.createElement('li');
+        li.textContent = d.description;
+        return li;
+    }));
+}
+
+function render_process(p) {
+    const [description, css_class] = process_state(p);
+    const state = document.getElementById('process_state');
+    state.textContent = description;
+    state.className = css_class;
+    document.getElementById('process_command').textContent = p.command_line;
+    document.getElementById('process_restarts').textContent = p.restarts > 0 ? `Restarted ${p.restarts} times after exiting` : "";
+
+    document.getElementById('process_exits_details').hidden = p.exits.length == 0;
+    document.getElementById('process_exits').replaceChildren(...p.exits.map(e => {
+        const row = document.createElement('tr');
+        cell(row, new Date(e.time).toLocaleString());
+        cell(row, exit_description(e));
+        cell(row, `${e.ran_for_s} s`);
+        cell(row, e.requested ? "stopped from the GUI" : "");
+        return row;
+    }));
+}
+
+async function btn_process(action) {
+    await post(instance_api(`process/${action}`), {});
+}
+
+// The saved changes that the running mux lacks
+function render_pending_config(pending) {
+    document.getElementById('pending_config_section').hidden = pending.live.length == 0 && pending.restart.length == 0;
+    document.getElementById('pending_live').hidden = pending.live.length == 0;
+    document.getElementById('pending_restart').hidden = pending.restart.length == 0;
+
+    const item = (text) => {
+        const li = document.createElement('li');
+        li.textContent = text;
+        return li;
+    };
+    document.getElementById('pending_live_list').replaceChildren(
+        ...pending.live.map(c => item(`${c.module} ${c.param}: ${c.value}`)));
+    document.getElementById('pending_restart_list').replaceChildren(
+        ...pending.restart.map(d => item(d.description)));
+}
+
+async function btn_apply_live() {
+    await post(instance_api('apply_live'), {});
+}
+
+// Same as Action::description on the server
+function action_description(a) {
+    switch (a.action) {
+        case "restart": return "Restart ODR-DabMux with the saved configuration";
+        case "apply_staged": return "Apply the staged configuration and restart ODR-DabMux";
+        default: return a.action;
+    }
+}
+
+// Same as ActionResult::description on the server
+function action_result_description(f) {
+    switch (f.result) {
+        case "done": return "Done";
+        case "failed": return `Failed: ${f.error}`;
+        case "rolled_back": return `Rolled back: ${f.reason}`;
+        default: return f.result;
+    }
+}
+
+function render_schedule(snapshot) {
+    const schedule = snapshot.schedule;
+    document.getElementById('schedule_table').hidden = schedule.length == 0;
+    document.getElementById('schedule_body').replaceChildren(...schedule.map(a => {
+        const row = document.createElement('tr');
+        cell(row, new Date(a.at).toLocaleString());
+        cell(row, action_description(a));
+        const cancel = document.createElement('button');
+        cancel.className = "btn";
+        cancel.type = "button";
+        cancel.textContent = "Cancel";
+        cancel.onclick = () => btn_cancel_scheduled(a.id);
+        cell(row, "").appendChild(cancel);
+        return row;
+    }));
+
+    const verifying = document.getElementById('schedule_verifying');
+    verifying.hidden = snapshot.verifying === null;
+    if (snapshot.verifying !== null) {
+        verifying.textContent = "Waiting for ODR-DabMux to come back healthy with the new configuration, until " +
+            `${new Date(snapshot.verifying.deadline).toLocaleString()}. The previous configuration is restored otherwise.`;
+    }
+
+    document.getElementById('staged_section').hidden = snapshot.staged === null;
+    if (snapshot.staged !== null) {
+        document.getElementById('staged_at').textContent = `on ${new Date(snapshot.staged.staged_at).toLocaleString()}`;
+        document.getElementById('staged_changes').replaceChildren(...snapshot.staged.changes.map(c => {
+            const li = document.createElement('li');
+            li.textContent = c;
+            return li;
+        }));
+    }
+
+    document.getElementById('schedule_history').hidden = snapshot.history.length == 0;
+    document.getElementById('schedule_history_body').replaceChildren(...snapshot.history.map(f => {
+        const row = document.createElement('tr');
+        cell(row, new Date(f.finished).toLocaleString());
+        cell(row, action_description(f));
+        cell(row, action_result_description(f));
+        return row;
+    }));
+}
+
+// The time chosen for a scheduled action, or null after telling the user to choose one
+function schedule_time() {
+    const value = document.getElementById('schedule_at').value;
+    if (value == "") {
+        alert("Choose the time of the action");
+        return null;
+    }
+    // The input is in local time
+    return new Date(value).toISOString();
+}
+
+async function btn_schedule_restart() {
+    const at = schedule_time();
+    if (at !== null) {
+        await post(instance_api('schedule'), { 'at': at, 'action': "restart" });
+    }
+}
+
+async function btn_schedule_apply_staged() {
+    const at = schedule_time();
+    const timeout = parseInt(document.getElementById('staged_health_timeout').value, 10);
+    if (at !== null) {
+        await post(instance_api('schedule'), { 'at': at, 'action': "apply_staged", 'health_timeout_s': timeout });
+    }
+}
+
+async function btn_discard_staged() {
+    const response = await fetch(instance_api('stage'), { method: "DELETE" });
+    if (!response.ok) {
+        alert(`Error discarding: ${response.statusText} ${await response.text()}`);
+    }
+}
+
+async function btn_cancel_scheduled(id) {
+    const response = await fetch(instance_api(`schedule/${id}`), { method: "DELETE" });
+    if (!response.ok) {
+        alert(`Error cancelling: ${response.statusText} ${await response.text()}`);
+    }
+}
+
+// Show the idents and RC modules that belong to a service with its label, and link to the service page
+function render_service_names(services) {
+    const by_ident = new Map(services.flatMap(s => s.idents.map(ident => [ident, s])));
+    const rename = (td, ident) => {
+        const s = by_ident.get(ident);
+        if (s === undefined) {
+            td.textContent = ident;
+            return;
+        }
+        const a = document.createElement('a');
+        a.href = `/instance/${encodeURIComponent(document.body.dataset.instance)}/service/${encodeURIComponent(s.unique_id)}`;
+        a.textContent = `${s.label} (${ident})`;
+        a.title = `SId ${s.sid}, ${s.bitrate} kbps, input port ${s.input_port}`;
+        td.replaceChildren(a);
+    };
+
+    for (const row of document.getElementById('stats_body').rows) {
+        rename(row.cells[0], row.dataset.ident);
+    }
+    for (const row of document.getElementById('params_body').rows) {
+        rename(row.cells[0], row.dataset.module);
+    }
+}
+
+// Show the state of the encoders the GUI runs next to the stats of their input
+function render_encoders(services) {
+    // The second ident is the subchannel, which names the input in the stats
+    const by_subchannel = new Map(services.map(s => [s.idents[1], s]));
+    for (const row of document.getElementById('stats_body').rows) {
+        const s = by_subchannel.get(row.dataset.ident);
+        const td = row.cells[1];
+        if (s === undefined || s.encoder === null) {
+            td.textContent = "";
+            td.className = "";
+            continue;
+        }
+        const [description, css_class] = process_state(encoder_state(s.encoder));
+        td.textContent = encoder_state(s.encoder).state;
+        td.title = description;
+        td.className = css_class;
+    }
+}
+
+document.addEventListener('DOMContentLoaded', () => {
+    load_saved_values();
+    connect_live(snapshot => {
+        render_stats(snapshot.stats, previous_snapshot?.stats);
+        render_params(snapshot.params);
+        render_drift(snapshot.drift);
+        render_service_names(snapshot.services);
+        render_encoders(snapshot.services);
+        render_pending_config(snapshot.pending);
+        render_schedule(snapshot);
+        render_process(snapshot.process);
+        previous_snapshot = snapshot;
+        update_ages();
+    });
+    setInterval(update_ages, 1000);
+});
@@ -0,0 +1,97 @@
+const LEVELS = ['debug', 'info', 'warn', 'error'];
+// Same as LOG_LINES on the server
+const MAX_LINES = 2000;
+
+// Lines received since the page was opened, and notes about lines that were missed
+let log_entries = [];
+let last_seq = null;
+
+function log_entry_matches(entry, min_level, search) {
+    if (entry.gap !== undefined) {
+        return true;
+    }
+    return LEVELS.indexOf(entry.level) >= min_level &&
+        (search == "" || entry.text.toLowerCase().includes(search));
+}
+
+function log_entry_element(entry) {
+    const div = document.createElement('div');
+    if (entry.gap !== undefined) {
+        div.textContent = `… ${entry.gap} lines skipped …`;
+        div.classList.add('log-gap');
+        return div;
+    }
+    const time = new Date(entry.time).toLocaleTimeString();
+    div.textContent = `${time} [${entry.source}] ${entry.text}`;
+    div.classList.add(`log-${entry.level}`);
+    return div;
+}
+
+function log_filter() {
+    return [
+        LEVELS.indexOf(document.getElementById('log_level').value),
+        document.getElementById('log_search').value.toLowerCase(),
+    ];
+}
+
+function scroll_log() {
+    if (document.getElementById('log_follow').checked) {
+        const container = document.getElementById('log_lines');
+        container.scrollTop = container.scrollHeight;
+    }
+}
+
+function render_log_count() {
+    const [min_level, search] = log_filter();
+    const shown = log_entries.filter(e => e.gap === undefined && log_entry_matches(e, min_level, search)).length;
+    const total = log_entries.filter(e => e.gap === undefined).length;
+    document.getElementById('log_shown').textContent = `Showing ${shown} of ${total} lines`;
+}
+
+function render_log() {
+    const [min_level, search] = log_filter();
+    document.getElementById('log_lines').replaceChildren(...log_entries
+        .filter(e => log_entry_matches(e, min_level, search))
+        .map(log_entry_element));
+    render_log_count();
+    scroll_log();
+}
+
+function append_log(lines) {
+    const [min_level, search] = log_filter();
+    const container = document.getElementById('log_lines');
+    for (const line of lines) {
+        // A slow connection misses lines the server could not keep for it
+        if (last_seq !== null && line.seq > last_seq + 1) {
+            const gap = { gap: line.seq - last_seq - 1 };
+            log_entries.push(gap);
+            container.appendChild(log_entry_element(gap));
+        }
+        last_seq = line.seq;
+        log_entries.push(line);
+        if (log_entry_matches(line, min_level, search)) {
+            container.appendChild(log_entry_element(line));
+        }
+    }
+
+    if (log_entries.length > MAX_LINES) {
+        log_entries = log_entries.slice(-MAX_LINES);
+        render_log();
+    }
+    else {
+        render_log_count();
+        scroll_log();
+    }
+}
+
+// The server sends all the lines it kept again after reconnecting
+function clear_log() {
+    log_entries = [];
+    last_seq = null;
+    render_log();
+}
+
+document.addEventListener('DOMContentLoaded', () => {
+    const api = document.querySelector('.content').dataset.logApi;
+    connect_live(append_log, `${api}/live`, clear_log);
+});
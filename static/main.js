@@ -1,3 +1,80 @@
+// URL of an API endpoint of the instance shown on the current page
+function instance_api(path) {
+    return `/api/instance/${encodeURIComponent(document.body.dataset.instance)}/${path}`;
+}
+
+// Same classification as Stats::health on the server
+function state_class(state) {
+    switch (state) {
+        case "Streaming": return "health-ok";
+        case "NoData": return "health-failed";
+        default: return "health-degraded";
+    }
+}
+
+function cell(row, text) {
+    const td = document.createElement('td');
+    td.textContent = text;
+    row.appendChild(td);
+    return td;
+}
+
+// Same formatting as InputStat::cells on the server
+function optional(v) {
+    return v ?? "N/A";
+}
+
+function level(v) {
+    return v === null ? "N/A" : `${v} dBFS`;
+}
+
+// Same as ProcessState::description and css_class on the server
+function process_state(p) {
+    switch (p.state) {
+        case "stopped": return ["Stopped", "health-unreachable"];
+        case "running": return [`Running with pid ${p.pid} since ${new Date(p.since).toLocaleString()}`, "health-ok"];
+        case "restarting": return [`Exited, restarting at ${new Date(p.at).toLocaleTimeString()} (attempt ${p.attempt})`, "health-degraded"];
+        default: return [p.error, "health-failed"];
+    }
+}
+
+// Same as EncoderStatus::state on the server
+function encoder_state(encoder) {
+    if (encoder.padenc !== null && encoder.audioenc.state == "running") {
+        return encoder.padenc;
+    }
+    return encoder.audioenc;
+}
+
+function exit_description(e) {
+    if (e.code !== null) {
+        return `exit code ${e.code}`;
+    }
+    return e.signal !== null ? `signal ${e.signal}` : "unknown";
+}
+
+// Receive the snapshots of the instance over a WebSocket, reconnecting when it closes.
+// The log viewer uses the same for the log lines, clearing its view in on_open.
+function connect_live(on_snapshot, endpoint = 'live', on_open = () => {}) {
+    const status = document.getElementById('live_status');
+    const scheme = window.location.protocol == "https:" ? "wss:" : "ws:";
+    const ws = new WebSocket(`${scheme}//${window.location.host}${instance_api(endpoint)}`);
+
+    ws.onopen = () => {
+        status.textContent = "Live";
+        status.classList.remove('health-unreachable');
+        on_open();
+    };
+
+    ws.onmessage = (event) => on_snapshot(JSON.parse(event.data));
+
+    // The server closes the connection when the instance is reconfigured
+    ws.onclose = () => {
+        status.textContent = "Disconnected, reconnecting…";
+        status.classList.add('health-unreachable');
+        setTimeout(() => connect_live(on_snapshot, endpoint, on_open), 2000);
+    };
+}
 
 async function post(url, data) {
     const params = {
@@ -0,0 +1,22 @@
+async function btn_overview_add_instance() {
+    const confirmation_element = document.getElementById('overview_confirmation');
+    confirmation_element.innerHTML = "";
+
+    const name = document.getElementById('new_instance_name').value;
+    const template = document.getElementById('new_instance_template').value;
+    const response = await fetch('/api/instances', {
+        method: "POST",
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify({ 'name': name, 'template': template == "" ? null : template }),
+    });
+
+    if (response.ok) {
+        window.location.href = `/instance/${encodeURIComponent(name)}/settings`;
+    }
+    else {
+        const validation = await response.json();
+        confirmation_element.textContent = validation.errors.map(e => e.message).join(", ");
+    }
+}
@@ -0,0 +1,107 @@
+// Idents of the stats and RC modules that belong to the service shown on this page
+function service_idents() {
+    return document.querySelector('.content').dataset.idents.split(' ');
+}
+
+function render_service_stats(cached) {
+    const stats = cached.value;
+    if (stats === null) {
+        return;
+    }
+    const idents = service_idents();
+    const input = stats.input_stats.find(([ident, _]) => idents.includes(ident));
+    document.getElementById('stats_missing').hidden = input !== undefined;
+
+    const body = document.getElementById('stats_body');
+    body.replaceChildren();
+    if (input === undefined) {
+        return;
+    }
+
+    const [_, is] = input;
+    const row = document.createElement('tr');
+    cell(row, optional(is.max_fill));
+    cell(row, optional(is.min_fill));
+    cell(row, optional(is.num_underruns));
+    cell(row, optional(is.num_overruns));
+    cell(row, level(is.peak_left));
+    cell(row, level(is.peak_right));
+    cell(row, level(is.peak_left_slow));
+    cell(row, level(is.peak_right_slow));
+    cell(row, optional(is.state)).classList.add(state_class(is.state));
+    cell(row, optional(is.version));
+    cell(row, optional(is.uptime));
+    cell(row, optional(is.last_tist_offset));
+
+    const known = ['max_fill', 'min_fill', 'num_underruns', 'num_overruns', 'peak_left', 'peak_right',
+        'peak_left_slow', 'peak_right_slow', 'state', 'version', 'uptime', 'last_tist_offset'];
+    cell(row, Object.entries(is)
+        .filter(([k, _]) => !known.includes(k))
+        .map(([k, v]) => `${k}=${JSON.stringify(v)}`)
+        .join(" "));
+    body.appendChild(row);
+}
+
+function render_service_params(cached) {
+    const params = cached.value;
+    if (params === null) {
+        return;
+    }
+    const idents = service_idents();
+    const body = document.getElementById('params_body');
+    body.replaceChildren(...params
+        .filter(p => idents.includes(p.module))
+        .map(p => {
+            const row = document.createElement('tr');
+            cell(row, p.module);
+            cell(row, p.param);
+            cell(row, p.write_only ? "(write-only)" : p.value);
+            return row;
+        }));
+}
+
+function render_encoder(services) {
+    const uid = document.querySelector('.content').dataset.uniqueId;
+    const encoder = services.find(s => s.unique_id == uid)?.encoder;
+    const body = document.getElementById('encoder_processes');
+    // Without encoders, the page says how to enable them
+    if (encoder === null || encoder === undefined || body === null) {
+        return;
+    }
+
+    const [description, css_class] = process_state(encoder_state(encoder));
+    const state = document.getElementById('encoder_state');
+    state.textContent = description;
+    state.className = css_class;
+
+    const processes = [["ODR-AudioEnc", encoder.audioenc]];
+    if (encoder.padenc !== null) {
+        processes.push(["ODR-PadEnc", encoder.padenc]);
+    }
+    body.replaceChildren(...processes.map(([name, p]) => {
+        const row = document.createElement('tr');
+        const [description, css_class] = process_state(p);
+        cell(row, name);
+        cell(row, description).className = css_class;
+        const code = document.createElement('code');
+        code.textContent = p.command_line;
+        cell(row, "").appendChild(code);
+        cell(row, p.restarts);
+        const last = p.exits[0];
+        cell(row, last === undefined ? "" : `${new Date(last.time).toLocaleString()}, ${exit_description(last)}`);
+        return row;
+    }));
+}
+
+async function btn_encoder(action) {
+    const uid = document.querySelector('.content').dataset.uniqueId;
+    await post(instance_api(`service/${encodeURIComponent(uid)}/encoder/${action}`), {});
+}
+
+document.addEventListener('DOMContentLoaded', () => {
+    connect_live(snapshot => {
+        render_encoder(snapshot.services);
+        render_service_stats(snapshot.stats);
+        render_service_params(snapshot.params);
+    });
+});
@@ -1,53 +1,368 @@
 async function btn_settings_add_service() {
     const template = document.getElementById('service_template');
+    const services = document.getElementById('services');
 
     let clon = template.content.cloneNode(true);
-    document.getElementById('services').appendChild(clon);
+
+    const opt = document.getElementById('new_service_template').selectedOptions[0];
+    if (opt && opt.value != "") {
+        clon.querySelector("input.srv_bitrate").value = opt.dataset.bitrate;
+        clon.querySelector("input.srv_protection").value = opt.dataset.protection;
+
+        let max_port = 0;
+        for (const port of services.querySelectorAll("input.srv_input_port")) {
+            max_port = Math.max(max_port, parseInt(port.value, 10) || 0);
+        }
+        if (max_port > 0) {
+            clon.querySelector("input.srv_input_port").value = max_port + 1;
+        }
+    }
+
+    services.appendChild(clon);
 }
 
 async function btn_settings_remove_service(element_clicked) {
     element_clicked.parentElement.remove()
 }
 
-async function btn_settings_send() {
+function service_enabled_changed(checkbox) {
+    checkbox.parentElement.classList.toggle("service-disabled", !checkbox.checked);
+}
+
+// Find the country option in the country picker that matches an ECC and country id, both in hex
+function find_country(ecc, id) {
+    for (const opt of document.getElementById('country').options) {
+        if (opt.dataset.ecc && opt.dataset.ecc == ecc.toUpperCase() &&
+                opt.dataset.ids.split(",").includes(id.toUpperCase())) {
+            return opt;
+        }
+    }
+    return null;
+}
+
+function country_changed() {
+    const opt = document.getElementById('country').selectedOptions[0];
+    if (!opt || !opt.dataset.ecc) {
+        return;
+    }
+
+    document.getElementById('ensemble_ecc').value = opt.dataset.ecc;
+
+    const eid = document.getElementById('ensemble_id');
+    const ids = opt.dataset.ids.split(",");
+    if (!ids.includes(eid.value.charAt(0).toUpperCase())) {
+        eid.value = ids[0] + eid.value.padStart(4, "0").substring(1);
+    }
+
+    check_country_ids();
+}
+
+// Mark the SIds whose country id doesn't match the ECC of the service, which is inherited from the ensemble
+// unless overridden
+function check_country_ids() {
+    const ensemble_ecc = document.getElementById('ensemble_ecc').value.trim();
+    const eid = document.getElementById('ensemble_id').value.trim();
+
+    const ensemble_country = find_country(ensemble_ecc, eid.charAt(0));
+    document.getElementById('country').value = ensemble_country ? ensemble_country.value : "";
+
+    let mismatches = 0;
+    for (const srv of document.getElementById('services').querySelectorAll("p.service")) {
+        const sid_element = srv.querySelector("input.srv_sid");
+        const srv_ecc = srv.querySelector("input.srv_ecc").value.trim();
+        const sid = sid_element.value.trim().padStart(4, "0");
+
+        const ecc = srv_ecc == "" ? ensemble_ecc : srv_ecc;
+        let ok;
+        if (srv_ecc != "") {
+            ok = find_country(srv_ecc, sid.charAt(0)) !== null;
+        }
+        else if (ensemble_country) {
+            ok = ensemble_country.dataset.ids.split(",").includes(sid.charAt(0).toUpperCase());
+        }
+        else {
+            ok = sid.charAt(0).toUpperCase() == eid.charAt(0).toUpperCase();
+        }
+
+        sid_element.classList.toggle("input-warning", !ok);
+        if (ok) {
+            sid_element.removeAttribute("title");
+        }
+        else {
+            sid_element.title = `Country id ${sid.charAt(0)} does not match ECC ${ecc}`;
+            mismatches++;
+        }
+    }
+
+    document.getElementById('country_check').textContent = mismatches > 0 ?
+        `${mismatches} service(s) have an SId whose country id does not match their ECC` : "";
+}
+
+// Parse an integer, returning null instead of NaN if the text is not entirely a valid number
+function parse_int_strict(text, radix) {
+    const re = (radix == 16) ? /^\s*[0-9a-fA-F]+\s*$/ : /^\s*-?[0-9]+\s*$/;
+    if (!re.test(text)) {
+        return null;
+    }
+    return parseInt(text, radix);
+}
+
+// Find the input element that corresponds to a field path as returned by the server,
+// e.g. "ensemble_label" or "services[2].sid"
+function field_element(field) {
+    const m = field.match(/^services\[(\d+)\]\.(\w+)$/);
+    if (m) {
+        const services = document.getElementById('services').querySelectorAll("p.service");
+        const index = parseInt(m[1], 10);
+        if (index < services.length) {
+            return services[index].querySelector("input.srv_" + m[2]);
+        }
+        return null;
+    }
+    else if (field != "") {
+        return document.getElementById(field);
+    }
+    return null;
+}
+
+function clear_field_marks() {
+    for (const el of document.querySelectorAll(".input-error, .input-warning")) {
+        el.classList.remove("input-error", "input-warning");
+        el.removeAttribute("title");
+    }
+}
+
+function show_issues(errors, warnings) {
+    const confirmation_element = document.getElementById('settings_send_confirmation');
+    const list = document.createElement("ul");
+    list.className = "issue-list";
+
+    const mark = (issue, cls) => {
+        const el = field_element(issue.field);
+        if (el) {
+            el.classList.add(cls);
+            el.title = el.title ? el.title + "\n" + issue.message : issue.message;
+        }
+
+        const li = document.createElement("li");
+        li.className = cls;
+        li.textContent = (issue.field ? issue.field + ": " : "") + issue.message;
+        list.appendChild(li);
+    };
+
+    errors.forEach(i => mark(i, "input-error"));
+    warnings.forEach(i => mark(i, "input-warning"));
+
+    if (list.children.length > 0) {
+        confirmation_element.appendChild(list);
+    }
+}
+
+// Tell what the running mux got through the remote control, and what needs a restart
+function show_applied(result) {
+    const confirmation_element = document.getElementById('settings_send_confirmation');
+    const add = (text, items) => {
+        const p = document.createElement("p");
+        p.textContent = text;
+        confirmation_element.appendChild(p);
+        if (items.length > 0) {
+            const list = document.createElement("ul");
+            list.className = "issue-list";
+            for (const item of items) {
+                const li = document.createElement("li");
+                li.textContent = item;
+                list.appendChild(li);
+            }
+            confirmation_element.appendChild(list);
+        }
+    };
+
+    if (result.applied) {
+        if (result.applied.ok) {
+            add("Applied to the running mux:", result.applied.results.map(r => `${r.module} ${r.param}: ${r.value}`));
+        }
+        else {
+            add("The running mux refused the changes:", result.applied.results
+                .filter(r => r.error !== undefined)
+                .map(r => `${r.module} ${r.param}: ${r.error}`));
+        }
+    }
+    if (result.apply_error) {
+        add(`Could not apply the changes to the running mux: ${result.apply_error}`, []);
+    }
+    if (result.pending.live.length > 0) {
+        add("Not applied yet, the Dashboard can apply them when the mux runs:",
+            result.pending.live.map(c => `${c.module} ${c.param}: ${c.value}`));
+    }
+    if (result.pending.restart.length > 0) {
+        add("These changes need a restart of ODR-DabMux, from the Dashboard now or at a scheduled time:",
+            result.pending.restart.map(d => d.description));
+    }
+}
+
+// Save the configuration, or stage it to be applied by a scheduled action from the Dashboard
+async function btn_settings_send(stage = false) {
     const confirmation_element = document.getElementById('settings_send_confirmation');
     confirmation_element.innerHTML = "";
+    clear_field_marks();
+
+    let parse_errors = [];
+    const read_int = (field, text, radix) => {
+        const value = parse_int_strict(text, radix);
+        if (value === null) {
+            parse_errors.push({
+                'field': field,
+                'message': (radix == 16) ? "Not a valid hexadecimal number" : "Not a valid number"
+            });
+        }
+        return value;
+    };
+    // An empty field means the value is inherited
+    const read_optional_int = (field, text, radix) => {
+        return (text.trim() == "") ? null : read_int(field, text, radix);
+    };
 
     let data = {
         'instance_name': document.getElementById('instance_name').value,
         'dabmux_config_location': document.getElementById('dabmux_config_location').value,
         'tist': document.getElementById('tist').checked,
-        'tist_offset': parseInt(document.getElementById('tist_offset').value, 10),
-        'ensemble_id': parseInt(document.getElementById('ensemble_id').value, 16),
-        'ensemble_ecc': parseInt(document.getElementById('ensemble_ecc').value, 16),
+        'tist_offset': read_int('tist_offset', document.getElementById('tist_offset').value, 10),
+        'ensemble_id': read_int('ensemble_id', document.getElementById('ensemble_id').value, 16),
+        'ensemble_ecc': read_int('ensemble_ecc', document.getElementById('ensemble_ecc').value, 16),
         'ensemble_label': document.getElementById('ensemble_label').value,
         'ensemble_shortlabel': document.getElementById('ensemble_shortlabel').value,
-        'output_edi_port': parseInt(document.getElementById('output_edi_port').value, 10),
-        'output_zmq_port': parseInt(document.getElementById('output_zmq_port').value, 10),
+        'output_edi_port': read_int('output_edi_port', document.getElementById('output_edi_port').value, 10),
+        'output_zmq_port': read_int('output_zmq_port', document.getElementById('output_zmq_port').value, 10),
+        'management_port': read_int('management_port', document.getElementById('management_port').value, 10),
+        'rc_telnet_port': read_int('rc_telnet_port', document.getElementById('rc_telnet_port').value, 10),
+        'rc_zmq_port': read_int('rc_zmq_port', document.getElementById('rc_zmq_port').value, 10),
+        'rc_transport': document.getElementById('rc_transport').value,
+        'mux_timeout_ms': read_int('mux_timeout_ms', document.getElementById('mux_timeout_ms').value, 10),
+        'mux_retries': read_int('mux_retries', document.getElementById('mux_retries').value, 10),
+        'poll_interval_ms': read_int('poll_interval_ms', document.getElementById('poll_interval_ms').value, 10),
+        'process': {
+            'binary': document.getElementById('process_binary').value,
+            'args': document.getElementById('process_args').value.split(/\s+/).filter(a => a != ""),
+            'autostart': document.getElementById('process_autostart').checked,
+            'auto_restart': document.getElementById('process_auto_restart').checked,
+            'log_file': document.getElementById('process_log_file').value,
+        },
+        'encoders': {
+            'host': document.getElementById('encoders_host').value,
+            'audioenc_binary': document.getElementById('encoders_audioenc_binary').value,
+            'padenc_binary': document.getElementById('encoders_padenc_binary').value,
+            'pad_directory': document.getElementById('encoders_pad_directory').value,
+        },
         'services': [],
     };
 
     const services = document.getElementById('services');
     const destList = services.querySelectorAll("p.service");
     for (let i = 0; i < destList.length; i++) {
+        const f = (name) => `services[${i}].${name}`;
         data.services.push({
             'unique_id': destList[i].querySelector("input.srv_unique_id").value,
-            'sid': parseInt(destList[i].querySelector("input.srv_sid").value, 16),
-            'ecc': parseInt(destList[i].querySelector("input.srv_ecc").value, 16),
+            'enabled': destList[i].querySelector("input.srv_enabled").checked,
+            'sid': read_int(f('sid'), destList[i].querySelector("input.srv_sid").value, 16),
+            'ecc': read_optional_int(f('ecc'), destList[i].querySelector("input.srv_ecc").value, 16),
             'label': destList[i].querySelector("input.srv_label").value,
             'shortlabel': destList[i].querySelector("input.srv_shortlabel").value,
-            'input_port': parseInt(destList[i].querySelector("input.srv_input_port").value, 10),
-            'bitrate': parseInt(destList[i].querySelector("input.srv_bitrate").value, 10),
-            'protection': parseInt(destList[i].querySelector("input.srv_protection").value, 10),
+            'input_port': read_int(f('input_port'), destList[i].querySelector("input.srv_input_port").value, 10),
+            'bitrate': read_int(f('bitrate'), destList[i].querySelector("input.srv_bitrate").value, 10),
+            'protection': read_int(f('protection'), destList[i].querySelector("input.srv_protection").value, 10),
+            'encoder': {
+                'managed': destList[i].querySelector("input.srv_encoder_managed").checked,
+                'source': destList[i].querySelector("select.srv_encoder_source").value,
+                'input': destList[i].querySelector("input.srv_encoder_input").value,
+                'profile': destList[i].querySelector("select.srv_encoder_profile").value,
+                'sample_rate': parseInt(destList[i].querySelector("select.srv_encoder_sample_rate").value, 10),
+                'pad': destList[i].querySelector("input.srv_encoder_pad").checked,
+                'autostart': destList[i].querySelector("input.srv_encoder_autostart").checked,
+                'auto_restart': destList[i].querySelector("input.srv_encoder_auto_restart").checked,
+            },
         });
     }
 
-    const success = await post('/api/settings', data);
-    if (success) {
+    if (parse_errors.length > 0) {
+        confirmation_element.innerHTML = "Configuration not written, please correct the marked fields";
+        show_issues(parse_errors, []);
+        return;
+    }
+
+    const params = {
+        method: "POST",
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify(data),
+    };
+
+    const response = await fetch(instance_api(stage ? 'stage' : 'settings'), params);
+    let validation = { 'errors': [], 'warnings': [] };
+    try {
+        validation = await response.json();
+    }
+    catch (e) {
+        validation.errors.push({ 'field': "", 'message': `${response.status} ${response.statusText}` });
+    }
+
+    if (response.ok && stage) {
+        confirmation_element.innerHTML = "Configuration staged, schedule when to apply it from the Dashboard";
+    }
+    else if (response.ok && data.instance_name != document.body.dataset.instance) {
+        // The instance was renamed, its pages moved
+        window.location.href = `/instance/${encodeURIComponent(data.instance_name)}/settings`;
+        return;
+    }
+    else if (response.ok) {
         confirmation_element.innerHTML = "Configuration successfully written";
+        show_applied(validation);
     }
     else {
         confirmation_element.innerHTML = "Failed to write config!";
     }
+    show_issues(validation.errors, validation.warnings);
+}
+
+async function btn_settings_delete_instance() {
+    const name = document.body.dataset.instance;
+    if (!confirm(`Delete instance ${name}? The ODR-DabMux config file will not be removed.`)) {
+        return;
+    }
+
+    const response = await fetch(`/api/instance/${encodeURIComponent(name)}`, { method: "DELETE" });
+    if (response.ok) {
+        window.location.href = '/';
+    }
+    else {
+        const validation = await response.json();
+        alert(`Error deleting instance: ${validation.errors.map(e => e.message).join(", ")}`);
+    }
 }
 
+async function btn_settings_save_template() {
+    const confirmation_element = document.getElementById('settings_send_confirmation');
+    confirmation_element.innerHTML = "";
+
+    const data = {
+        'name': document.getElementById('template_name').value,
+        'description': document.getElementById('template_description').value,
+    };
+
+    const response = await fetch(instance_api('save_template'), {
+        method: "POST",
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify(data),
+    });
+    const validation = await response.json();
+
+    if (response.ok) {
+        confirmation_element.innerHTML = "Template saved";
+    }
+    else {
+        confirmation_element.innerHTML = "Failed to save template!";
+    }
+    show_issues(validation.errors, validation.warnings);
+}
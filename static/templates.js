@@ -0,0 +1,59 @@
+async function show_templates_result(response) {
+    const confirmation_element = document.getElementById('templates_confirmation');
+    const validation = await response.json();
+    if (response.ok && validation.warnings.length == 0) {
+        window.location.reload();
+    }
+    else {
+        confirmation_element.textContent =
+            validation.errors.concat(validation.warnings).map(e => e.message).join(", ");
+    }
+}
+
+async function btn_templates_delete(kind, name) {
+    if (!confirm(`Delete template ${name}?`)) {
+        return;
+    }
+
+    const response = await fetch(`/api/templates/${kind}/${encodeURIComponent(name)}`, { method: "DELETE" });
+    await show_templates_result(response);
+}
+
+async function btn_templates_add_service() {
+    const data = {
+        'name': document.getElementById('srv_template_name').value,
+        'description': document.getElementById('srv_template_description').value,
+        'bitrate': parseInt(document.getElementById('srv_template_bitrate').value, 10),
+        'protection': parseInt(document.getElementById('srv_template_protection').value, 10),
+    };
+
+    if (isNaN(data.bitrate) || isNaN(data.protection)) {
+        document.getElementById('templates_confirmation').textContent = "Bitrate and protection must be numbers";
+        return;
+    }
+
+    const response = await fetch('/api/templates/service', {
+        method: "POST",
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify(data),
+    });
+    await show_templates_result(response);
+}
+
+async function btn_templates_import() {
+    const files = document.getElementById('import_file').files;
+    if (files.length == 0) {
+        return;
+    }
+
+    const response = await fetch('/api/templates/import', {
+        method: "POST",
+        headers: {
+            'Content-Type': 'application/toml'
+        },
+        body: await files[0].text(),
+    });
+    await show_templates_result(response);
+}
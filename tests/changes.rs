@@ -0,0 +1,58 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Classification of configuration changes
+
+mod common;
+
+use common::test_config;
+
+#[test]
+fn live_and_restart() {
+    let running = test_config("changes");
+    assert!(running.changes_since(&running).is_empty());
+
+    let mut conf = running.clone();
+    conf.ensemble_label = "New Ensemble".to_owned();
+    conf.services[0].label = "Renamed".to_owned();
+    conf.services[0].shortlabel = "Ren".to_owned();
+    conf.tist_offset = 2;
+    let changes = conf.changes_since(&running);
+    assert!(changes.restart.is_empty(), "{:?}", changes.restart);
+    let live : Vec<String> = changes.live.iter().map(|c| format!("{}.{}={}", c.module, c.param, c.value)).collect();
+    assert_eq!(live, ["ensemble.label=New Ensemble,Mock", "mux.tist_offset=2", "srv-station1.label=Renamed,Ren"]);
+
+    conf.services[1].bitrate = 96;
+    conf.output_edi_port += 1;
+    let changes = conf.changes_since(&running);
+    assert_eq!(changes.live.len(), 3);
+    let paths : Vec<&str> = changes.restart.iter().map(|d| d.path.as_str()).collect();
+    assert!(paths.contains(&"subchannels.sub-station2.bitrate"), "{:?}", paths);
+    assert_eq!(paths.len(), 2, "{:?}", paths);
+}
+
+#[test]
+fn new_service_needs_restart() {
+    let running = test_config("changes");
+    let mut conf = running.clone();
+    let mut service = conf.services[0].clone();
+    service.unique_id = "station3".to_owned();
+    service.sid = 0x4003;
+    service.input_port = 9003;
+    conf.services.push(service);
+
+    let changes = conf.changes_since(&running);
+    assert!(changes.live.is_empty());
+    assert!(changes.restart.iter().any(|d| d.path == "services.srv-station3"), "{:?}", changes.restart);
+}
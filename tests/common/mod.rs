@@ -0,0 +1,471 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A mock ODR-DabMux for the integration tests: a management server answering `info`, `values`
+//! and `getptree`, and a ZMQ remote control answering `showjson`, `list`, `get` and `set`.
+//! The same parameters are also served by a telnet remote control answering `list`, `show`, `get` and `set`.
+//! Its state can be changed while it runs, to script values, latency, timeouts and errors.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use serde_json::{json, Map, Value};
+
+use odr_dabmux_gui::{
+    config::{Config, Service},
+    dabmux::{ClientSettings, RcEndpoint},
+    DabMux,
+};
+
+#[derive(Debug, Clone)]
+pub struct MockParam {
+    pub value : String,
+    pub description : String,
+    /// Listed, but left out of showjson like the parameters ODR-DabMux cannot read back
+    pub write_only : bool,
+}
+
+impl MockParam {
+    pub fn new(value: &str, description: &str) -> Self {
+        Self { value: value.to_owned(), description: description.to_owned(), write_only: false }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockState {
+    /// `service` in the answer to info, which the client checks
+    pub service : String,
+    pub version : String,
+    pub uptime : Option<u64>,
+    /// Contents of `values` in the answer to values
+    pub values : Value,
+    /// Answer to getptree
+    pub ptree : Value,
+    /// RC parameters by module and name
+    pub modules : BTreeMap<String, BTreeMap<String, MockParam>>,
+    /// Delay before every answer
+    pub latency : Duration,
+    /// Leave all requests unanswered
+    pub silent : bool,
+    /// Answer all requests with something that is not JSON
+    pub garbage : bool,
+    /// Modules missing from the answer to list
+    pub unlisted : BTreeSet<String>,
+    /// Error messages of show over telnet, by module
+    pub refuse_show : BTreeMap<String, String>,
+    /// Error messages of set, by `module.param`
+    pub refuse_set : BTreeMap<String, String>,
+    /// Every request received, with its parts
+    pub requests : Vec<Vec<String>>,
+}
+
+impl MockState {
+    pub fn param(&self, module: &str, param: &str) -> Option<&str> {
+        self.modules.get(module)?.get(param).map(|p| p.value.as_str())
+    }
+
+    pub fn set_param(&mut self, module: &str, param: &str, value: &str) {
+        self.modules.entry(module.to_owned()).or_default()
+            .entry(param.to_owned())
+            .and_modify(|p| p.value = value.to_owned())
+            .or_insert_with(|| MockParam::new(value, ""));
+    }
+
+    pub fn set_input_stat(&mut self, ident: &str, inputstat: Value) {
+        self.values[ident] = json!({ "inputstat": inputstat });
+    }
+
+    /// Number of requests received for a command, e.g. "set"
+    pub fn count(&self, command: &str) -> usize {
+        self.requests.iter().filter(|r| r[0] == command).count()
+    }
+}
+
+/// The state of a mux running the configuration returned by `test_config`
+impl Default for MockState {
+    fn default() -> Self {
+        let mut modules : BTreeMap<String, BTreeMap<String, MockParam>> = BTreeMap::new();
+        let mut add = |module: &str, param: &str, p: MockParam| {
+            modules.entry(module.to_owned()).or_default().insert(param.to_owned(), p);
+        };
+        add("ensemble", "label", MockParam::new("Mock Ensemble", "Label of the ensemble"));
+        add("ensemble", "shortlabel", MockParam::new("Mock", "Short label of the ensemble"));
+        add("mux", "frames", MockParam::new("1234", "Transmitted frames [read-only]"));
+        add("mux", "tist_offset", MockParam::new("0", "Timestamp offset in seconds"));
+        for (uid, label, shortlabel) in [("station1", "Station 1", "Stn 1"), ("station2", "Station 2", "Stn 2")] {
+            add(&format!("srv-{}", uid), "label", MockParam::new(label, "Label of the service"));
+            add(&format!("srv-{}", uid), "shortlabel", MockParam::new(shortlabel, "Short label of the service"));
+            add(&format!("srv-{}", uid), "pty", MockParam::new("0", "Programme type"));
+            add(&format!("sub-{}", uid), "buffer", MockParam::new("40", "Size of the input buffer"));
+            add(&format!("sub-{}", uid), "enable", MockParam::new("true", "Whether the input is enabled"));
+        }
+        add("sub-station1", "secret", MockParam {
+            value: "hidden".to_owned(),
+            description: "A parameter that cannot be read".to_owned(),
+            write_only: true,
+        });
+
+        Self {
+            service: "ODR-DabMux v5.0.0 MGMT Server".to_owned(),
+            version: "v5.0.0-mock".to_owned(),
+            uptime: Some(3600),
+            values: json!({
+                "sub-station1": { "inputstat": {
+                    "min_fill": 10, "max_fill": 40,
+                    "peak_left": -12, "peak_right": -13, "peak_left_slow": -10, "peak_right_slow": -11,
+                    "num_underruns": 0, "num_overruns": 0,
+                    "state": "Streaming", "version": "ODR-AudioEnc mock", "uptime": 100, "last_tist_offset": 0,
+                }},
+                "sub-station2": { "inputstat": { "state": "NoData" } },
+                "clock": { "drift": 3 },
+            }),
+            ptree: json!({ "general": { "dabmode": "1" } }),
+            modules,
+            latency: Duration::ZERO,
+            silent: false,
+            garbage: false,
+            unlisted: BTreeSet::new(),
+            refuse_show: BTreeMap::new(),
+            refuse_set: BTreeMap::new(),
+            requests: Vec::new(),
+        }
+    }
+}
+
+fn management_answer(state: &MockState, request: &[String]) -> Vec<String> {
+    let answer = match request[0].as_str() {
+        "info" => {
+            let mut info = json!({ "service": state.service, "version": state.version });
+            if let Some(uptime) = state.uptime {
+                info["uptime"] = json!(uptime);
+            }
+            info
+        },
+        "values" => json!({ "values": state.values }),
+        "getptree" => state.ptree.clone(),
+        _ => return vec!["Unknown command".to_owned()],
+    };
+    vec![answer.to_string()]
+}
+
+fn rc_answer(state: &mut MockState, request: &[String]) -> Vec<String> {
+    let fail = |message: String| vec!["fail".to_owned(), message];
+
+    match request {
+        [cmd] if cmd == "showjson" => {
+            let all : Map<String, Value> = state.modules.iter()
+                .map(|(module, params)| {
+                    let values : Map<String, Value> = params.iter()
+                        .filter(|(_, p)| !p.write_only)
+                        .map(|(name, p)| (name.clone(), Value::String(p.value.clone())))
+                        .collect();
+                    (module.clone(), Value::Object(values))
+                })
+                .collect();
+            vec![Value::Object(all).to_string()]
+        },
+        [cmd] if cmd == "list" => {
+            state.modules.iter()
+                .filter(|(module, _)| !state.unlisted.contains(*module))
+                .map(|(module, params)| {
+                    let descriptions : Map<String, Value> = params.iter()
+                        .map(|(name, p)| (name.clone(), Value::String(p.description.clone())))
+                        .collect();
+                    json!({ "name": module, "params": descriptions }).to_string()
+                })
+                .collect()
+        },
+        [cmd, module, param] if cmd == "get" => match state.param(module, param) {
+            Some(value) => vec![value.to_owned()],
+            None => fail(format!("{}.{} not found", module, param)),
+        },
+        [cmd, module, param, value] if cmd == "set" => {
+            if let Some(message) = state.refuse_set.get(&format!("{}.{}", module, param)) {
+                return fail(message.clone());
+            }
+            if state.param(module, param).is_none() {
+                return fail(format!("{}.{} not found", module, param));
+            }
+            // Like ODR-DabMux, labels are set together with their short label, if the module has one
+            let has_shortlabel = state.param(module, "shortlabel").is_some();
+            match (param.as_str(), value.split_once(',')) {
+                ("label", Some((label, shortlabel))) if has_shortlabel => {
+                    state.set_param(module, "label", label);
+                    state.set_param(module, "shortlabel", shortlabel);
+                },
+                ("label", None) if has_shortlabel => return fail("Expected label,shortlabel".to_owned()),
+                _ => state.set_param(module, param, value),
+            }
+            vec!["ok".to_owned()]
+        },
+        _ => fail(format!("Unknown command {}", request.join(" "))),
+    }
+}
+
+type Handler = fn(&mut MockState, &[String]) -> Vec<String>;
+
+// A ROUTER socket answers REQ clients like a REP socket, but can also leave a request unanswered
+fn serve(ctx: &zmq::Context, state: Arc<Mutex<MockState>>, stop: Arc<AtomicBool>, handler: Handler) -> (u16, JoinHandle<()>) {
+    let sock = ctx.socket(zmq::ROUTER).unwrap();
+    sock.set_linger(0).unwrap();
+    sock.bind("tcp://127.0.0.1:*").unwrap();
+    let endpoint = sock.get_last_endpoint().unwrap().unwrap();
+    let port = endpoint.rsplit(':').next().unwrap().parse().unwrap();
+
+    let thread = thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            let mut items = [ sock.as_poll_item(zmq::POLLIN), ];
+            zmq::poll(&mut items, 50).unwrap();
+            if !items[0].is_readable() {
+                continue;
+            }
+
+            // Identity of the client, empty delimiter, then the request
+            let mut parts = sock.recv_multipart(0).unwrap();
+            let envelope : Vec<Vec<u8>> = parts.drain(..2).collect();
+            let request : Vec<String> = parts.into_iter()
+                .map(|p| String::from_utf8(p).unwrap())
+                .collect();
+
+            let (answer, latency) = {
+                let mut st = state.lock().unwrap();
+                st.requests.push(request.clone());
+                if st.silent {
+                    continue;
+                }
+                let answer = if st.garbage { vec!["this is not JSON".to_owned()] } else { handler(&mut st, &request) };
+                (answer, st.latency)
+            };
+
+            thread::sleep(latency);
+            let reply : Vec<Vec<u8>> = envelope.into_iter()
+                .chain(answer.into_iter().map(String::into_bytes))
+                .collect();
+            // The client may have given up and closed its socket
+            let _ = sock.send_multipart(reply, zmq::DONTWAIT);
+        }
+    });
+    (port, thread)
+}
+
+// The telnet remote control answers a line with text followed by a prompt
+fn telnet_answer(state: &mut MockState, line: &str) -> String {
+    let request : Vec<String> = line.splitn(4, ' ').map(str::to_owned).collect();
+    match request.as_slice() {
+        [cmd] if cmd == "list" => {
+            let mut answer = String::new();
+            for (module, params) in state.modules.iter().filter(|(module, _)| !state.unlisted.contains(*module)) {
+                answer += &format!("{}\n", module);
+                for (name, p) in params {
+                    answer += &format!("  {} : {}\n", name, p.description);
+                }
+            }
+            answer
+        },
+        [cmd, module] if cmd == "show" => {
+            if let Some(message) = state.refuse_show.get(module) {
+                return format!("{}\n", message);
+            }
+            match state.modules.get(module) {
+                Some(params) => params.iter()
+                    .filter(|(_, p)| !p.write_only)
+                    .map(|(name, p)| format!("{} : {}\n", name, p.value))
+                    .collect(),
+                None => format!("Error: module {} not found\n", module),
+            }
+        },
+        // Same answers as over ZMQ, where the mux prints the error message instead of fail
+        _ => match rc_answer(state, &request).as_slice() {
+            [fail, message] if fail == "fail" => format!("Error: {}\n", message),
+            answer => format!("{}\n", answer.join("\n")),
+        },
+    }
+}
+
+fn serve_telnet_client(stream: TcpStream, state: Arc<Mutex<MockState>>, stop: Arc<AtomicBool>) {
+    stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    if writer.write_all(b"ODR-DabMux mock telnet remote control\n> ").is_err() {
+        return;
+    }
+
+    let mut line = String::new();
+    while !stop.load(Ordering::Relaxed) {
+        match reader.read_line(&mut line) {
+            Ok(0) => return,
+            Ok(_) if line.ends_with('\n') => (),
+            // Timed out, or only part of the line arrived
+            _ => continue,
+        }
+
+        let request = line.trim().to_owned();
+        line.clear();
+        let (answer, latency) = {
+            let mut st = state.lock().unwrap();
+            st.requests.push(request.split(' ').map(str::to_owned).collect());
+            if st.silent {
+                continue;
+            }
+            (telnet_answer(&mut st, &request), st.latency)
+        };
+
+        thread::sleep(latency);
+        if writer.write_all(format!("{}> ", answer).as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+fn serve_telnet(state: Arc<Mutex<MockState>>, stop: Arc<AtomicBool>) -> (u16, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let thread = thread::spawn(move || {
+        let mut clients = Vec::new();
+        while !stop.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false).unwrap();
+                    let (state, stop) = (state.clone(), stop.clone());
+                    clients.push(thread::spawn(move || serve_telnet_client(stream, state, stop)));
+                },
+                Err(_) => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+        for c in clients {
+            let _ = c.join();
+        }
+    });
+    (port, thread)
+}
+
+/// A running mock mux. It stops when dropped.
+pub struct MockMux {
+    pub management_port : u16,
+    pub rc_port : u16,
+    pub telnet_port : u16,
+    state : Arc<Mutex<MockState>>,
+    stop : Arc<AtomicBool>,
+    threads : Vec<JoinHandle<()>>,
+}
+
+impl MockMux {
+    pub fn start(state: MockState) -> Self {
+        let ctx = zmq::Context::new();
+        let state = Arc::new(Mutex::new(state));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (management_port, management) = serve(&ctx, state.clone(), stop.clone(), |st, req| management_answer(st, req));
+        let (rc_port, rc) = serve(&ctx, state.clone(), stop.clone(), rc_answer);
+        let (telnet_port, telnet) = serve_telnet(state.clone(), stop.clone());
+
+        Self { management_port, rc_port, telnet_port, state, stop, threads: vec![management, rc, telnet] }
+    }
+
+    /// Change or inspect the state while the mux runs
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    /// A configuration pointing to this mux, with short timeouts
+    pub fn config(&self, name: &str) -> Config {
+        let mut conf = test_config(name);
+        conf.management_port = self.management_port;
+        conf.rc_zmq_port = self.rc_port;
+        conf
+    }
+
+    pub fn dabmux(&self) -> DabMux {
+        let conf = self.config("mock");
+        DabMux::new(&RcEndpoint::Zmq(format!("tcp://127.0.0.1:{}", self.rc_port)), &conf.stats_endpoint(), settings())
+    }
+
+    /// A client using the telnet remote control
+    pub fn telnet_dabmux(&self) -> DabMux {
+        let conf = self.config("mock");
+        DabMux::new(&RcEndpoint::Telnet(format!("127.0.0.1:{}", self.telnet_port)), &conf.stats_endpoint(), settings())
+    }
+}
+
+impl Drop for MockMux {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for t in self.threads.drain(..) {
+            let _ = t.join();
+        }
+    }
+}
+
+pub fn settings() -> ClientSettings {
+    ClientSettings { timeout: Duration::from_millis(200), retries: 1 }
+}
+
+/// An ensemble with the services the default MockState knows about
+pub fn test_config(name: &str) -> Config {
+    let service = |uid: &str, sid: u32, label: &str, shortlabel: &str, input_port: u16| Service {
+        unique_id: uid.to_owned(),
+        enabled: true,
+        sid,
+        ecc: None,
+        label: label.to_owned(),
+        shortlabel: shortlabel.to_owned(),
+        input_port,
+        bitrate: 72,
+        protection: 3,
+        encoder: Default::default(),
+    };
+
+    Config {
+        instance_name: name.to_owned(),
+        ensemble_label: "Mock Ensemble".to_owned(),
+        ensemble_shortlabel: "Mock".to_owned(),
+        mux_timeout_ms: 200,
+        mux_retries: 1,
+        poll_interval_ms: 100,
+        services: vec![
+            service("station1", 0x4001, "Station 1", "Stn 1", 9001),
+            service("station2", 0x4002, "Station 2", "Stn 2", 9002),
+        ],
+        ..Default::default()
+    }
+}
+
+/// A directory for the files of one test, removed when dropped
+pub struct TestDir {
+    pub path : std::path::PathBuf,
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("odr-dabmux-gui-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
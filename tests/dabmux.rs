@@ -0,0 +1,284 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The mux client against the mock mux
+
+mod common;
+
+use std::time::Duration;
+use serde_json::json;
+
+use odr_dabmux_gui::{
+    dabmux::{Error, Health, RcChange},
+    DabMuxHandle, ParamKind,
+};
+use common::{MockMux, MockState};
+
+fn change(module: &str, param: &str, value: &str) -> RcChange {
+    RcChange { module: module.to_owned(), param: param.to_owned(), value: value.to_owned() }
+}
+
+#[test]
+fn stats() {
+    let mock = MockMux::start(MockState::default());
+    let stats = mock.dabmux().get_stats().unwrap();
+
+    assert_eq!(stats.version, "v5.0.0-mock");
+    assert_eq!(stats.uptime, Some(3600));
+    let idents : Vec<&str> = stats.input_stats.iter().map(|(ident, _)| ident.as_str()).collect();
+    assert_eq!(idents, ["sub-station1", "sub-station2"]);
+
+    let streaming = &stats.input_stats[0].1;
+    assert_eq!(streaming.max_fill, Some(40));
+    assert_eq!(streaming.state.as_deref(), Some("Streaming"));
+    // Missing values are not an error
+    assert_eq!(stats.input_stats[1].1.max_fill, None);
+
+    assert_eq!(stats.other_values["clock"], json!({ "drift": 3 }));
+    assert_eq!(stats.health(), Health::Failed);
+}
+
+#[test]
+fn stats_of_unknown_inputs() {
+    let mut state = MockState::default();
+    state.set_input_stat("sub-station2", json!({ "state": "Streaming", "edi_frames": 12 }));
+    let mock = MockMux::start(state);
+
+    let stats = mock.dabmux().get_stats().unwrap();
+    assert_eq!(stats.input_stats[1].1.extra["edi_frames"], json!(12));
+    assert_eq!(stats.health(), Health::Ok);
+}
+
+#[test]
+fn stats_of_another_service() {
+    let state = MockState { service: "ODR-AudioEnc".to_owned(), ..Default::default() };
+    let mock = MockMux::start(state);
+
+    assert!(matches!(mock.dabmux().get_stats(), Err(Error::Protocol(_))));
+}
+
+#[test]
+fn rc_parameters() {
+    let mock = MockMux::start(MockState::default());
+    let params = mock.dabmux().get_rc_parameters().unwrap();
+    let find = |module: &str, param: &str| params.iter()
+        .find(|p| p.module == module && p.param == param)
+        .unwrap_or_else(|| panic!("{}.{} missing", module, param));
+
+    // Label and short label are edited together
+    let label = find("srv-station1", "label");
+    assert_eq!(label.value, "Station 1,Stn 1");
+    assert_eq!(label.kind, ParamKind::LabelPair);
+    assert!(!params.iter().any(|p| p.param == "shortlabel"));
+
+    // Reported as text, sent as 0 or 1
+    let enable = find("sub-station1", "enable");
+    assert_eq!(enable.value, "1");
+    assert_eq!(enable.kind, ParamKind::Bool);
+
+    assert_eq!(find("mux", "frames").kind, ParamKind::ReadOnly);
+    assert_eq!(find("sub-station1", "buffer").description.as_deref(), Some("Size of the input buffer"));
+
+    let secret = find("sub-station1", "secret");
+    assert!(secret.write_only);
+    assert_eq!(secret.value, "");
+}
+
+#[test]
+fn rc_descriptions_fetched_once() {
+    let mut state = MockState::default();
+    state.unlisted.insert("sub-station2".to_owned());
+    let mock = MockMux::start(state);
+    let mut dabmux = mock.dabmux();
+
+    for _ in 0..3 {
+        let params = dabmux.get_rc_parameters().unwrap();
+        assert!(params.iter().any(|p| p.module == "sub-station2" && p.description.is_none()));
+    }
+    assert_eq!(mock.state().count("list"), 1);
+
+    // A new module appears when the mux is restarted with another configuration
+    mock.state().set_param("srv-station3", "pty", "0");
+    dabmux.get_rc_parameters().unwrap();
+    assert_eq!(mock.state().count("list"), 2);
+}
+
+#[test]
+fn set_and_get() {
+    let mock = MockMux::start(MockState::default());
+    let mut dabmux = mock.dabmux();
+
+    dabmux.set_rc_parameter("srv-station1", "label", "New name,New").unwrap();
+    assert_eq!(mock.state().param("srv-station1", "shortlabel"), Some("New"));
+    assert_eq!(dabmux.get_rc_parameter("srv-station1", "label").unwrap(), "New name");
+
+    assert!(matches!(dabmux.get_rc_parameter("srv-nothing", "label"), Err(Error::Refused(_))));
+}
+
+#[test]
+fn set_refused() {
+    let mut state = MockState::default();
+    state.refuse_set.insert("mux.tist_offset".to_owned(), "Offset out of range".to_owned());
+    let mock = MockMux::start(state);
+
+    match mock.dabmux().set_rc_parameter("mux", "tist_offset", "100") {
+        Err(Error::Refused(e)) => assert_eq!(e, "Offset out of range"),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(mock.state().param("mux", "tist_offset"), Some("0"));
+}
+
+#[test]
+fn garbage_answers() {
+    let mock = MockMux::start(MockState { garbage: true, ..Default::default() });
+    let mut dabmux = mock.dabmux();
+
+    assert!(matches!(dabmux.get_stats(), Err(Error::Protocol(_))));
+    assert!(matches!(dabmux.get_rc_parameters(), Err(Error::Protocol(_))));
+    assert!(matches!(dabmux.get_running_config(), Err(Error::Protocol(_))));
+}
+
+#[test]
+fn timeout_and_recovery() {
+    let mock = MockMux::start(MockState { silent: true, ..Default::default() });
+    let mut dabmux = mock.dabmux();
+
+    assert!(matches!(dabmux.get_stats(), Err(Error::Timeout)));
+    // First attempt and one retry
+    assert_eq!(mock.state().count("info"), 2);
+
+    // The unanswered socket was replaced, so the next request goes through
+    mock.state().silent = false;
+    assert!(dabmux.get_stats().is_ok());
+}
+
+#[test]
+fn latency() {
+    let mock = MockMux::start(MockState { latency: Duration::from_millis(100), ..Default::default() });
+    assert!(mock.dabmux().get_running_config().is_ok());
+
+    // Longer than the timeout of the client, on every attempt
+    mock.state().latency = Duration::from_millis(300);
+    assert!(matches!(mock.dabmux().get_running_config(), Err(Error::Timeout)));
+}
+
+#[test]
+fn running_config() {
+    let mock = MockMux::start(MockState::default());
+    assert_eq!(mock.dabmux().get_running_config().unwrap(), json!({ "general": { "dabmode": "1" } }));
+
+    mock.state().ptree = json!("not an object");
+    assert!(matches!(mock.dabmux().get_running_config(), Err(Error::Protocol(_))));
+}
+
+#[test]
+fn telnet() {
+    let mock = MockMux::start(MockState::default());
+    let mut dabmux = mock.telnet_dabmux();
+
+    let params = dabmux.get_rc_parameters().unwrap();
+    let label = params.iter().find(|p| p.module == "srv-station1" && p.param == "label").unwrap();
+    assert_eq!(label.value, "Station 1,Stn 1");
+    assert_eq!(label.kind, ParamKind::LabelPair);
+
+    dabmux.set_rc_parameter("srv-station1", "label", "New Label,New").unwrap();
+    assert_eq!(dabmux.get_rc_parameter("srv-station1", "label").unwrap(), "New Label");
+    assert!(matches!(dabmux.get_rc_parameter("srv-nothing", "label"), Err(Error::Refused(_))));
+}
+
+#[test]
+fn telnet_error_reply() {
+    let mut state = MockState::default();
+    state.refuse_show.insert("sub-station1".to_owned(), "Error: module not found".to_owned());
+    let mock = MockMux::start(state);
+
+    // Not taken for a parameter named Error
+    match mock.telnet_dabmux().get_rc_parameters() {
+        Err(Error::Refused(e)) => assert_eq!(e, "Error: module not found"),
+        other => panic!("Expected the error of the mux, got {:?}", other),
+    }
+}
+
+#[test]
+fn batch_applied() {
+    let mock = MockMux::start(MockState::default());
+    let result = mock.dabmux().apply_rc_batch(&[
+        change("ensemble", "label", "Other,Oth"),
+        change("srv-station2", "pty", "10"),
+    ]);
+
+    assert!(result.ok);
+    assert_eq!(result.results[0].previous.as_deref(), Some("Mock Ensemble,Mock"));
+    assert_eq!(mock.state().param("ensemble", "label"), Some("Other"));
+    assert_eq!(mock.state().param("srv-station2", "pty"), Some("10"));
+}
+
+#[test]
+fn batch_rolled_back() {
+    let mut state = MockState::default();
+    state.refuse_set.insert("srv-station2.pty".to_owned(), "Not now".to_owned());
+    let mock = MockMux::start(state);
+
+    let result = mock.dabmux().apply_rc_batch(&[
+        change("srv-station1", "pty", "5"),
+        change("srv-station2", "pty", "10"),
+        change("mux", "tist_offset", "2"),
+    ]);
+
+    assert!(!result.ok);
+    let statuses : Vec<String> = result.results.iter()
+        .map(|r| serde_json::to_value(r).unwrap()["status"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(statuses, ["rolled_back", "failed", "not_applied"]);
+    assert_eq!(mock.state().param("srv-station1", "pty"), Some("0"));
+    assert_eq!(mock.state().param("mux", "tist_offset"), Some("0"));
+}
+
+#[test]
+fn batch_label_without_shortlabel() {
+    let mut state = MockState::default();
+    state.modules.get_mut("srv-station2").unwrap().remove("shortlabel");
+    let mock = MockMux::start(state);
+
+    let result = mock.dabmux().apply_rc_batch(&[change("srv-station2", "label", "Other")]);
+    assert!(result.ok, "{:?}", serde_json::to_value(&result).unwrap());
+    assert_eq!(result.results[0].previous.as_deref(), Some("Station 2"));
+    assert_eq!(mock.state().param("srv-station2", "label"), Some("Other"));
+}
+
+#[test]
+fn batch_invalid() {
+    let mock = MockMux::start(MockState::default());
+    let result = mock.dabmux().apply_rc_batch(&[
+        change("srv-station1", "pty", "5"),
+        change("srv-station2", "pty", "99"),
+    ]);
+
+    // Nothing is sent to the mux when a value is invalid
+    assert!(!result.ok);
+    assert_eq!(mock.state().count("set"), 0);
+}
+
+#[tokio::test]
+async fn handle() {
+    let mock = MockMux::start(MockState::default());
+    let handle = DabMuxHandle::spawn("mock", mock.dabmux());
+
+    handle.set_rc_parameter("sub-station1", "buffer", "60").await.unwrap();
+    assert_eq!(handle.get_rc_parameter("sub-station1", "buffer").await.unwrap(), "60");
+    assert_eq!(handle.get_stats().await.unwrap().input_stats.len(), 2);
+
+    mock.state().silent = true;
+    assert!(matches!(handle.get_stats().await, Err(Error::Timeout)));
+}
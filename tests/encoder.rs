@@ -0,0 +1,149 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The encoder commands of a service, and their supervision with shell commands standing in for the encoders
+
+mod common;
+
+use std::time::Duration;
+
+use odr_dabmux_gui::{
+    config::encoders::{AacProfile, AudioSource},
+    encoder::{Encoder, EncoderSettings, EncoderStatus},
+    muxlog::MuxLog,
+    supervisor::{ProcessState, SupervisorSettings},
+};
+use common::test_config;
+
+#[test]
+fn commands() {
+    let mut conf = test_config("enc");
+    let default = conf.services[0].audioenc_command(&conf);
+    assert!(default.contains(&"--device=default".to_owned()));
+    assert!(default.contains(&"--pad-socket=enc-station1".to_owned()));
+    assert!(conf.services[0].padenc_command(&conf).is_some());
+
+    let enc = &mut conf.services[0].encoder;
+    enc.source = AudioSource::Stream;
+    enc.input = "http://example.com/stream.mp3".to_owned();
+    enc.profile = AacProfile::HeAacV2;
+    enc.sample_rate = 32000;
+    enc.pad = false;
+    let s = &conf.services[0];
+    let cmd = s.audioenc_command(&conf);
+    for arg in ["--vlc-uri=http://example.com/stream.mp3", "--ps", "--rate=32000", "--edi=tcp://127.0.0.1:9001"] {
+        assert!(cmd.contains(&arg.to_owned()), "{} missing in {:?}", arg, cmd);
+    }
+    assert!(!cmd.iter().any(|a| a.starts_with("--pad")));
+    assert!(s.padenc_command(&conf).is_none());
+    assert!(s.encoder_settings(&conf).padenc.is_none());
+
+    // No PadEnc unit for a service without PAD
+    let names : Vec<String> = conf.encoder_files().into_iter().map(|f| f.name).collect();
+    assert!(!names.contains(&"odr-padenc-enc-station1.service".to_owned()));
+    assert!(names.contains(&"odr-padenc-enc-station2.service".to_owned()));
+}
+
+#[test]
+fn systemd_units() {
+    let mut conf = test_config("enc");
+    let enc = &mut conf.services[0].encoder;
+    enc.source = AudioSource::Stream;
+    enc.input = "http://example.com/live?token=$HOME&rate=100%".to_owned();
+    let unit = conf.encoder_files().into_iter()
+        .find(|f| f.name == "odr-audioenc-enc-station1.service")
+        .unwrap();
+    // Neither expanded as an environment variable nor as a specifier by systemd
+    assert!(unit.contents.contains("--vlc-uri=http://example.com/live?token=$$HOME&rate=100%%"), "{}", unit.contents);
+}
+
+#[test]
+fn validation() {
+    let mut conf = test_config("enc");
+    conf.services[0].encoder.managed = true;
+    conf.services[0].encoder.input = " ".to_owned();
+    conf.services[1].encoder.sample_rate = 44100;
+    conf.encoders.padenc_binary = String::new();
+
+    let v = conf.validate();
+    let fields : Vec<&str> = v.errors.iter().map(|i| i.field.as_str()).collect();
+    assert!(fields.contains(&"services[0].encoder_input"), "{:?}", fields);
+    assert!(fields.contains(&"services[1].encoder_sample_rate"), "{:?}", fields);
+    assert!(fields.contains(&"encoders_padenc_binary"), "{:?}", fields);
+}
+
+#[cfg(unix)]
+fn shell(script: &str) -> SupervisorSettings {
+    SupervisorSettings {
+        program: "sh".to_owned(),
+        args: vec!["-c".to_owned(), script.to_owned()],
+        auto_restart: false,
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for(encoder: &Encoder, condition: impl Fn(&EncoderStatus) -> bool) -> EncoderStatus {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let status = encoder.status();
+            if condition(&status) {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.expect("timeout waiting for the encoder status")
+}
+
+#[cfg(unix)]
+fn running(state: &ProcessState) -> bool {
+    matches!(state, ProcessState::Running { .. })
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn start_stop_together() {
+    let mut encoder = Encoder::spawn("test", EncoderSettings {
+        audioenc: shell("exec sleep 30"),
+        padenc: Some(shell("exec sleep 30")),
+    }, MuxLog::default());
+
+    encoder.start();
+    let status = wait_for(&encoder, |s| s.processes().iter().all(|(_, p)| running(&p.state))).await;
+    assert_eq!(status.processes().len(), 2);
+
+    // Removing PAD stops ODR-PadEnc, adding it again starts it next to the running ODR-AudioEnc
+    encoder.reconfigure(EncoderSettings { audioenc: shell("exec sleep 30"), padenc: None });
+    assert!(encoder.status().padenc.is_none());
+    encoder.reconfigure(EncoderSettings { audioenc: shell("exec sleep 30"), padenc: Some(shell("exec sleep 30")) });
+    wait_for(&encoder, |s| s.padenc.as_ref().is_some_and(|p| running(&p.state))).await;
+
+    encoder.stop();
+    let status = wait_for(&encoder, |s| s.processes().iter().all(|(_, p)| p.state == ProcessState::Stopped)).await;
+    assert!(status.audioenc.exits[0].requested);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn state_of_failed_padenc() {
+    let encoder = Encoder::spawn("test", EncoderSettings {
+        audioenc: shell("exec sleep 30"),
+        padenc: Some(shell("exit 1")),
+    }, MuxLog::default());
+
+    encoder.start();
+    let status = wait_for(&encoder, |s| running(&s.audioenc.state) && s.padenc.as_ref().is_some_and(|p| !p.exits.is_empty())).await;
+    // The service gets no PAD, so the encoder is not considered running
+    assert_eq!(*status.state(), ProcessState::Stopped);
+    encoder.stop();
+}
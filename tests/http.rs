@@ -0,0 +1,610 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The pages and APIs of the GUI binary, with instances connected to the mock mux
+
+#![cfg(feature = "gui")]
+
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use serde_json::{json, Value};
+
+use odr_dabmux_gui::{config::GuiConfig, Config};
+use common::{MockMux, MockState, TestDir};
+
+/// The GUI binary, running in its own directory with one instance per mock mux
+struct Gui {
+    port : u16,
+    child : Child,
+    dir : TestDir,
+}
+
+impl Gui {
+    fn start(test: &str, mocks: &[(&str, &MockMux)]) -> Self {
+        Self::start_with(test, mocks, |_| ())
+    }
+
+    /// Start with instance configurations changed by `configure`
+    fn start_with(test: &str, mocks: &[(&str, &MockMux)], configure: impl Fn(&mut Config)) -> Self {
+        let dir = gui_dir(test, mocks, configure);
+        let (port, child) = Self::spawn(&dir);
+        Self { port, child, dir }
+    }
+
+    fn spawn(dir: &TestDir) -> (u16, Child) {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let child = Command::new(env!("CARGO_BIN_EXE_odr-dabmux-gui"))
+            .args(["-p", &port.to_string()])
+            .current_dir(&dir.path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        wait_until("the GUI listens", || TcpStream::connect(("127.0.0.1", port)).is_ok());
+        (port, child)
+    }
+
+    /// Stop the GUI and start it again with the configuration it stored
+    fn restart(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        (self.port, self.child) = Self::spawn(&self.dir);
+    }
+
+    fn request(&self, method: &str, path: &str, body: Option<&Value>) -> (u16, String) {
+        let body = body.map(Value::to_string).unwrap_or_default();
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        write!(stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method, path, body.len(), body).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, body.to_owned())
+    }
+
+    fn get(&self, path: &str) -> (u16, String) {
+        self.request("GET", path, None)
+    }
+
+    fn post(&self, path: &str, body: Value) -> (u16, String) {
+        self.request("POST", path, Some(&body))
+    }
+
+    fn snapshot(&self, name: &str) -> Value {
+        let (status, body) = self.get(&format!("/api/instance/{}/snapshot", name));
+        assert_eq!(status, 200, "{}", body);
+        serde_json::from_str(&body).unwrap()
+    }
+
+    /// Wait for the poller to have read both the stats and the RC parameters
+    fn polled_snapshot(&self, name: &str) -> Value {
+        wait_until("the mux is polled", || {
+            let s = self.snapshot(name);
+            !s["stats"]["value"].is_null() && !s["params"]["value"].is_null()
+        });
+        self.snapshot(name)
+    }
+}
+
+/// A directory with the configuration of the GUI, with one instance per mock mux
+fn gui_dir(test: &str, mocks: &[(&str, &MockMux)], configure: impl Fn(&mut Config)) -> TestDir {
+    let dir = TestDir::new(test);
+    let instances = mocks.iter()
+        .map(|(name, mock)| {
+            let mut conf = mock.config(name);
+            conf.dabmux_config_location = dir.path.join(format!("{}.json", name)).display().to_string();
+            configure(&mut conf);
+            conf
+        })
+        .collect();
+    let gui_conf = GuiConfig { instances, templates: Default::default(), schedules: Default::default() };
+    std::fs::write(dir.path.join("odr-dabmux-gui-config.toml"), toml::to_string_pretty(&gui_conf).unwrap()).unwrap();
+    dir
+}
+
+impl Drop for Gui {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn wait_until(what: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timeout waiting until {}", what);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn snapshot() {
+    let mock = MockMux::start(MockState::default());
+    let gui = Gui::start("snapshot", &[("mock", &mock)]);
+
+    let s = gui.polled_snapshot("mock");
+    assert_eq!(s["stats"]["value"]["version"], "v5.0.0-mock");
+    assert_eq!(s["stats"]["value"]["input_stats"][0][0], "sub-station1");
+    assert!(s["params"]["value"].as_array().unwrap().iter()
+        .any(|p| p["module"] == "srv-station1" && p["param"] == "label" && p["value"] == "Station 1,Stn 1"));
+    assert_eq!(s["services"][0]["idents"], json!(["srv-station1", "sub-station1", "comp-station1"]));
+
+    let (status, _) = gui.get("/api/instance/nothing/snapshot");
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn unreachable_mux() {
+    let mock = MockMux::start(MockState { silent: true, ..Default::default() });
+    let gui = Gui::start("unreachable", &[("mock", &mock)]);
+
+    wait_until("the error is published", || !gui.snapshot("mock")["stats"]["error"].is_null());
+    let (status, body) = gui.get("/api/instance/mock/rc/mux/tist_offset");
+    assert_eq!(status, 502, "{}", body);
+}
+
+#[test]
+fn set_rc() {
+    let mut state = MockState::default();
+    state.refuse_set.insert("srv-station2.pty".to_owned(), "Not now".to_owned());
+    let mock = MockMux::start(state);
+    let gui = Gui::start("set_rc", &[("mock", &mock)]);
+
+    let (status, body) = gui.post("/api/instance/mock/set_rc", json!({ "module": "srv-station1", "param": "pty", "value": "10" }));
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(mock.state().param("srv-station1", "pty"), Some("10"));
+    assert_eq!(gui.get("/api/instance/mock/rc/srv-station1/pty"), (200, "10".to_owned()));
+
+    // Checked before it is sent
+    let (status, _) = gui.post("/api/instance/mock/set_rc", json!({ "module": "srv-station1", "param": "pty", "value": "99" }));
+    assert_eq!(status, 400);
+    assert_eq!(mock.state().count("set"), 1);
+
+    let (status, body) = gui.post("/api/instance/mock/set_rc", json!({ "module": "srv-station2", "param": "pty", "value": "3" }));
+    assert_eq!(status, 400);
+    assert!(body.contains("Not now"), "{}", body);
+}
+
+#[test]
+fn set_rc_label_without_shortlabel() {
+    let mut state = MockState::default();
+    state.modules.get_mut("srv-station2").unwrap().remove("shortlabel");
+    let mock = MockMux::start(state);
+    let gui = Gui::start("set_rc_label_without_shortlabel", &[("mock", &mock)]);
+    gui.polled_snapshot("mock");
+
+    let (status, body) = gui.post("/api/instance/mock/set_rc", json!({ "module": "srv-station2", "param": "label", "value": "Other" }));
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(mock.state().param("srv-station2", "label"), Some("Other"));
+
+    // Labels with a short label still need both
+    let (status, _) = gui.post("/api/instance/mock/set_rc", json!({ "module": "srv-station1", "param": "label", "value": "Other" }));
+    assert_eq!(status, 400);
+}
+
+#[test]
+fn set_rc_persist() {
+    let mock = MockMux::start(MockState::default());
+    let gui = Gui::start("persist", &[("mock", &mock)]);
+
+    let (status, body) = gui.post("/api/instance/mock/set_rc",
+        json!({ "module": "srv-station1", "param": "label", "value": "Renamed,Ren", "persist": true }));
+    assert_eq!(status, 200, "{}", body);
+
+    let (_, saved) = gui.get("/api/instance/mock/saved_rc");
+    let saved : Value = serde_json::from_str(&saved).unwrap();
+    assert!(saved.as_array().unwrap().iter()
+        .any(|v| v["module"] == "srv-station1" && v["value"] == "Renamed,Ren"));
+
+    let gui_conf = std::fs::read_to_string(gui.dir.path.join("odr-dabmux-gui-config.toml")).unwrap();
+    assert!(gui_conf.contains("label = \"Renamed\""));
+    let dabmux_json = std::fs::read_to_string(gui.dir.path.join("mock.json")).unwrap();
+    assert!(dabmux_json.contains("Renamed"));
+
+    // Not part of the configuration, so it cannot be saved
+    let (status, _) = gui.post("/api/instance/mock/set_rc",
+        json!({ "module": "srv-station1", "param": "pty", "value": "1", "persist": true }));
+    assert_eq!(status, 400);
+    assert_eq!(mock.state().param("srv-station1", "pty"), Some("0"));
+
+    // Values that cannot be saved are not sent to the mux either
+    let (status, _) = gui.post("/api/instance/mock/set_rc",
+        json!({ "module": "mux", "param": "tist_offset", "value": "soon", "persist": true }));
+    assert_eq!(status, 400);
+    let (status, body) = gui.post("/api/instance/mock/set_rc",
+        json!({ "module": "srv-station1", "param": "label", "value": "A label that is far too long,Label", "persist": true }));
+    assert_eq!(status, 400);
+    assert!(body.contains("longer than"), "{}", body);
+    let (status, _) = gui.post("/api/instance/mock/set_rc_batch", json!({ "persist": true, "changes": [
+        { "module": "mux", "param": "tist_offset", "value": "2" },
+        { "module": "ensemble", "param": "label", "value": "Ensemble,Mock" },
+    ]}));
+    assert_eq!(status, 400);
+    assert_eq!(mock.state().count("set"), 1);
+    assert_eq!(mock.state().param("mux", "tist_offset"), Some("0"));
+}
+
+#[test]
+fn set_rc_batch() {
+    let mut state = MockState::default();
+    state.refuse_set.insert("srv-station2.pty".to_owned(), "Not now".to_owned());
+    let mock = MockMux::start(state);
+    let gui = Gui::start("batch", &[("mock", &mock)]);
+
+    let (status, body) = gui.post("/api/instance/mock/set_rc_batch", json!({ "changes": [
+        { "module": "srv-station1", "param": "pty", "value": "5" },
+        { "module": "mux", "param": "tist_offset", "value": "1" },
+    ]}));
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(mock.state().param("mux", "tist_offset"), Some("1"));
+
+    let (status, body) = gui.post("/api/instance/mock/set_rc_batch", json!({ "changes": [
+        { "module": "srv-station1", "param": "pty", "value": "6" },
+        { "module": "srv-station2", "param": "pty", "value": "7" },
+    ]}));
+    assert_eq!(status, 400);
+    let result : Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(result["ok"], false);
+    assert_eq!(result["results"][0]["status"], "rolled_back");
+    assert_eq!(result["results"][1]["error"], "Mux refused the request: Not now");
+    assert_eq!(mock.state().param("srv-station1", "pty"), Some("5"));
+}
+
+#[test]
+fn pages() {
+    let mock = MockMux::start(MockState::default());
+    let other = MockMux::start(MockState::default());
+    let gui = Gui::start("pages", &[("mock", &mock), ("other", &other)]);
+    gui.polled_snapshot("mock");
+
+    let (status, body) = gui.get("/");
+    assert_eq!(status, 200);
+    assert!(body.contains("/instance/other"));
+
+    let (status, body) = gui.get("/instance/mock");
+    assert_eq!(status, 200);
+    assert!(body.contains("Station 1 (sub-station1)"), "{}", body);
+
+    let (status, body) = gui.get("/instance/mock/service/station1");
+    assert_eq!(status, 200);
+    assert!(body.contains("ODR-AudioEnc mock"), "{}", body);
+
+    assert_eq!(gui.get("/instance/mock/service/nothing").0, 404);
+    assert_eq!(gui.get("/instance/nothing").0, 404);
+}
+
+#[test]
+fn write_encoders() {
+    let mock = MockMux::start(MockState::default());
+    let dir = gui_dir("write_encoders", &[("mock", &mock)], |_| ());
+    let status = Command::new(env!("CARGO_BIN_EXE_odr-dabmux-gui"))
+        .args(["--write-encoders", "out"])
+        .current_dir(&dir.path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(dir.path.join("out/mock/encoders.sh").exists());
+    // The instances are not set up, so nothing polls the mux
+    assert!(mock.state().requests.is_empty());
+}
+
+#[test]
+fn process() {
+    let mock = MockMux::start(MockState::default());
+    let gui = Gui::start_with("process", &[("mock", &mock)], |conf| {
+        // The configuration file is given as $0 of the script
+        conf.process.binary = "sh".to_owned();
+        conf.process.args = vec!["-c".to_owned(), "exec sleep 30".to_owned()];
+    });
+    assert_eq!(gui.snapshot("mock")["process"]["state"], "stopped");
+
+    let (status, body) = gui.post("/api/instance/mock/process/start", json!({}));
+    assert_eq!(status, 200, "{}", body);
+    // Starting writes the generated configuration
+    assert!(gui.dir.path.join("mock.json").exists());
+    wait_until("odr-dabmux runs", || gui.snapshot("mock")["process"]["state"] == "running");
+
+    gui.post("/api/instance/mock/process/stop", json!({}));
+    wait_until("odr-dabmux stops", || gui.snapshot("mock")["process"]["state"] == "stopped");
+    let s = gui.snapshot("mock");
+    assert_eq!(s["process"]["exits"][0]["requested"], true);
+
+    assert_eq!(gui.post("/api/instance/mock/process/explode", json!({})).0, 404);
+}
+
+#[test]
+fn log() {
+    let mock = MockMux::start(MockState::default());
+    let gui = Gui::start_with("log", &[("mock", &mock)], |conf| {
+        conf.process.binary = "sh".to_owned();
+        conf.process.args = vec!["-c".to_owned(), "echo 'WARN: mock mux output'".to_owned()];
+        conf.process.auto_restart = false;
+        // Next to the generated configuration, in the directory of the test
+        conf.process.log_file = conf.dabmux_config_location.replace("mock.json", "mux.log");
+    });
+    // Tailed once it appears
+    std::fs::write(gui.dir.path.join("mux.log"), "first line\n").unwrap();
+
+    gui.post("/api/instance/mock/process/start", json!({}));
+    wait_until("the output is captured", || gui.get("/api/instance/mock/log.txt").1.contains("[stdout] WARN WARN: mock mux output"));
+    wait_until("the log file is read", || gui.get("/api/instance/mock/log.txt").1.contains("[file] INFO first line"));
+
+    let (status, body) = gui.get("/instance/mock/log");
+    assert_eq!(status, 200);
+    assert!(body.contains("mux.log"), "{}", body);
+    assert_eq!(gui.get("/api/instance/nothing/log.txt").0, 404);
+}
+
+#[test]
+fn encoder() {
+    let mock = MockMux::start(MockState::default());
+    let gui = Gui::start_with("encoder", &[("mock", &mock)], |conf| {
+        // Exits right away, whatever the arguments
+        conf.encoders.audioenc_binary = "true".to_owned();
+        conf.services[0].encoder.managed = true;
+        conf.services[0].encoder.pad = false;
+        conf.services[0].encoder.auto_restart = false;
+    });
+    let s = gui.snapshot("mock");
+    assert_eq!(s["services"][0]["encoder"]["audioenc"]["state"], "stopped");
+    assert!(s["services"][0]["encoder"]["padenc"].is_null());
+    assert!(s["services"][1]["encoder"].is_null());
+
+    let (status, body) = gui.post("/api/instance/mock/service/station1/encoder/start", json!({}));
+    assert_eq!(status, 200, "{}", body);
+    wait_until("the encoder exits", || gui.snapshot("mock")["services"][0]["encoder"]["audioenc"]["exits"][0]["code"] == 0);
+    assert!(gui.get("/api/instance/mock/service/station1/log.txt").1.contains("Started true --device=default"));
+
+    let (status, body) = gui.get("/instance/mock/service/station1");
+    assert_eq!(status, 200);
+    assert!(body.contains("ODR-AudioEnc"), "{}", body);
+
+    assert_eq!(gui.post("/api/instance/mock/service/station2/encoder/start", json!({})).0, 404);
+    assert_eq!(gui.get("/instance/mock/service/station2/log").0, 404);
+    assert_eq!(gui.post("/api/instance/mock/service/station1/encoder/explode", json!({})).0, 404);
+}
+
+#[test]
+fn settings_write_failed() {
+    let mock = MockMux::start(MockState::default());
+    let gui = Gui::start("write_failed", &[("mock", &mock)]);
+
+    let mut conf = mock.config("mock");
+    conf.dabmux_config_location = gui.dir.path.join("missing/mock.json").display().to_string();
+    conf.ensemble_label = "Mock Not Saved".to_owned();
+    let (status, body) = gui.post("/api/instance/mock/settings", serde_json::to_value(&conf).unwrap());
+    assert_eq!(status, 500, "{}", body);
+
+    // Neither stored nor used
+    let stored = std::fs::read_to_string(gui.dir.path.join("odr-dabmux-gui-config.toml")).unwrap();
+    assert!(!stored.contains("Not Saved"), "{}", stored);
+    assert!(!gui.get("/instance/mock/settings").1.contains("Not Saved"));
+}
+
+#[test]
+fn settings_not_applied_live() {
+    let mock = MockMux::start(MockState { silent: true, ..Default::default() });
+    let gui = Gui::start("not_live", &[("mock", &mock)]);
+
+    // Whether the mux was not polled yet or did not answer, the changes are not attempted
+    let mut conf = mock.config("mock");
+    conf.dabmux_config_location = gui.dir.path.join("mock.json").display().to_string();
+    conf.services[0].label = "Renamed".to_owned();
+    conf.services[0].shortlabel = "Ren".to_owned();
+    let (status, body) = gui.post("/api/instance/mock/settings", serde_json::to_value(&conf).unwrap());
+    assert_eq!(status, 200, "{}", body);
+
+    let result : Value = serde_json::from_str(&body).unwrap();
+    assert!(result["applied"].is_null(), "{}", body);
+    assert!(result["apply_error"].is_string(), "{}", body);
+    assert_eq!(result["pending"]["live"][0]["module"], "srv-station1", "{}", body);
+    assert_eq!(mock.state().count("set"), 0);
+}
+
+#[test]
+fn settings_applied_live() {
+    let mock = MockMux::start(MockState::default());
+    let gui = Gui::start("live", &[("mock", &mock)]);
+    gui.polled_snapshot("mock");
+
+    let mut conf = mock.config("mock");
+    conf.dabmux_config_location = gui.dir.path.join("mock.json").display().to_string();
+    conf.services[0].label = "Renamed".to_owned();
+    conf.services[0].shortlabel = "Ren".to_owned();
+    conf.services[1].bitrate = 96;
+    let (status, body) = gui.post("/api/instance/mock/settings", serde_json::to_value(&conf).unwrap());
+    assert_eq!(status, 200, "{}", body);
+
+    let result : Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(result["applied"]["ok"], true, "{}", body);
+    assert_eq!(mock.state().param("srv-station1", "label"), Some("Renamed"));
+    assert!(result["pending"]["live"].as_array().unwrap().is_empty(), "{}", body);
+    assert_eq!(result["pending"]["restart"][0]["path"], "subchannels.sub-station2.bitrate");
+    assert_eq!(gui.snapshot("mock")["pending"], result["pending"]);
+}
+
+#[test]
+fn schedule() {
+    let mock = MockMux::start(MockState::default());
+    let gui = Gui::start("schedule", &[("mock", &mock)]);
+
+    let at = chrono::Utc::now() + chrono::Duration::hours(1);
+    let (status, body) = gui.post("/api/instance/mock/schedule", json!({ "at": at, "action": "restart" }));
+    assert_eq!(status, 200, "{}", body);
+    let s = gui.snapshot("mock");
+    assert_eq!(s["schedule"][0]["action"], "restart");
+    assert_eq!(s["schedule"][0]["id"].to_string(), body);
+
+    assert_eq!(gui.request("DELETE", &format!("/api/instance/mock/schedule/{}", body), None).0, 200);
+    assert!(gui.snapshot("mock")["schedule"].as_array().unwrap().is_empty());
+    assert_eq!(gui.request("DELETE", &format!("/api/instance/mock/schedule/{}", body), None).0, 404);
+
+    let past = chrono::Utc::now() - chrono::Duration::hours(1);
+    assert_eq!(gui.post("/api/instance/mock/schedule", json!({ "at": past, "action": "restart" })).0, 400);
+}
+
+/// A staged configuration with another bitrate and ODR-DabMux started with `script`, applied in a second
+fn apply_staged(test: &str, script: &str) -> (Gui, MockMux) {
+    let mock = MockMux::start(MockState::default());
+    let gui = Gui::start_with(test, &[("mock", &mock)], |conf| {
+        conf.process.binary = "sh".to_owned();
+        conf.process.args = vec!["-c".to_owned(), "exec sleep 30".to_owned()];
+        conf.process.auto_restart = false;
+    });
+    assert_eq!(gui.post("/api/instance/mock/schedule", json!({ "at": chrono::Utc::now() + chrono::Duration::hours(1), "action": "apply_staged" })).0, 400);
+
+    gui.post("/api/instance/mock/process/start", json!({}));
+    wait_until("odr-dabmux runs", || gui.snapshot("mock")["process"]["state"] == "running");
+
+    let mut conf = mock.config("mock");
+    conf.dabmux_config_location = gui.dir.path.join("mock.json").display().to_string();
+    conf.process.binary = "sh".to_owned();
+    conf.process.args = vec!["-c".to_owned(), script.to_owned()];
+    conf.process.auto_restart = false;
+    conf.services[1].bitrate = 96;
+    let (status, body) = gui.post("/api/instance/mock/stage", serde_json::to_value(&conf).unwrap());
+    assert_eq!(status, 200, "{}", body);
+    assert!(gui.snapshot("mock")["staged"]["changes"][0].as_str().unwrap().contains("bitrate"));
+
+    let at = chrono::Utc::now() + chrono::Duration::seconds(1);
+    let (status, body) = gui.post("/api/instance/mock/schedule", json!({ "at": at, "action": "apply_staged", "health_timeout_s": 5 }));
+    assert_eq!(status, 200, "{}", body);
+    // Only once for one staged configuration
+    let later = chrono::Utc::now() + chrono::Duration::hours(1);
+    assert_eq!(gui.post("/api/instance/mock/schedule", json!({ "at": later, "action": "apply_staged" })).0, 409);
+    wait_until("the staged configuration is verified", || !gui.snapshot("mock")["history"].as_array().unwrap().is_empty());
+    (gui, mock)
+}
+
+fn written_bitrate(gui: &Gui) -> Value {
+    let json : Value = serde_json::from_str(&std::fs::read_to_string(gui.dir.path.join("mock.json")).unwrap()).unwrap();
+    json["subchannels"]["sub-station2"]["bitrate"].clone()
+}
+
+#[test]
+fn staged_applied() {
+    let (gui, _mock) = apply_staged("staged_applied", "exec sleep 30");
+    let s = gui.snapshot("mock");
+    assert_eq!(s["history"][0]["result"], "done", "{}", s["history"]);
+    assert!(s["staged"].is_null());
+    assert_eq!(written_bitrate(&gui), 96);
+    // Saved as the configuration of the instance
+    let stored = std::fs::read_to_string(gui.dir.path.join("odr-dabmux-gui-config.toml")).unwrap();
+    assert!(stored.contains("bitrate = 96"), "{}", stored);
+}
+
+#[test]
+fn staged_rolled_back() {
+    let (gui, _mock) = apply_staged("staged_rolled_back", "exit 1");
+    let s = gui.snapshot("mock");
+    assert_eq!(s["history"][0]["result"], "rolled_back", "{}", s["history"]);
+    assert_ne!(written_bitrate(&gui), 96);
+    // Running again with the previous command
+    wait_until("odr-dabmux runs", || gui.snapshot("mock")["process"]["state"] == "running");
+    assert!(gui.snapshot("mock")["process"]["command_line"].as_str().unwrap().contains("sleep 30"));
+
+    let (status, body) = gui.get("/instance/mock");
+    assert_eq!(status, 200);
+    assert!(body.contains("Rolled back: ODR-DabMux exited with exit code 1"), "{}", body);
+}
+
+#[test]
+fn staged_clash() {
+    let mock = MockMux::start(MockState::default());
+    let other = MockMux::start(MockState::default());
+    let gui = Gui::start_with("staged_clash", &[("mock", &mock), ("other", &other)], |conf| {
+        if conf.instance_name == "other" {
+            conf.output_edi_port += 1000;
+            conf.output_zmq_port += 1000;
+            conf.rc_telnet_port += 1000;
+            for s in &mut conf.services {
+                s.input_port += 1000;
+            }
+        }
+    });
+
+    let mut staged = mock.config("mock");
+    staged.dabmux_config_location = gui.dir.path.join("mock.json").display().to_string();
+    staged.output_edi_port = 9300;
+    let (status, body) = gui.post("/api/instance/mock/stage", serde_json::to_value(&staged).unwrap());
+    assert_eq!(status, 200, "{}", body);
+
+    // The other instance takes the port before the staged configuration is applied
+    let stored : GuiConfig = toml::from_str(&std::fs::read_to_string(gui.dir.path.join("odr-dabmux-gui-config.toml")).unwrap()).unwrap();
+    let mut conf = stored.instances.into_iter().find(|c| c.instance_name == "other").unwrap();
+    conf.output_edi_port = 9300;
+    let (status, body) = gui.post("/api/instance/other/settings", serde_json::to_value(&conf).unwrap());
+    assert_eq!(status, 200, "{}", body);
+
+    let at = chrono::Utc::now() + chrono::Duration::seconds(1);
+    gui.post("/api/instance/mock/schedule", json!({ "at": at, "action": "apply_staged" }));
+    wait_until("the action failed", || !gui.snapshot("mock")["history"].as_array().unwrap().is_empty());
+    let s = gui.snapshot("mock");
+    assert_eq!(s["history"][0]["result"], "failed", "{}", s["history"]);
+    assert!(s["history"][0]["error"].as_str().unwrap().contains("Port 9300"), "{}", s["history"]);
+    // Still staged, to be corrected
+    assert!(!s["staged"].is_null());
+    assert_eq!(gui.snapshot("mock")["process"]["state"], "stopped");
+}
+
+#[test]
+fn schedule_persisted() {
+    let mock = MockMux::start(MockState::default());
+    let mut gui = Gui::start("schedule_persisted", &[("mock", &mock)]);
+
+    let mut staged = mock.config("mock");
+    staged.dabmux_config_location = gui.dir.path.join("mock.json").display().to_string();
+    staged.services[1].bitrate = 96;
+    assert_eq!(gui.post("/api/instance/mock/stage", serde_json::to_value(&staged).unwrap()).0, 200);
+    let later = chrono::Utc::now() + chrono::Duration::hours(1);
+    assert_eq!(gui.post("/api/instance/mock/schedule", json!({ "at": later, "action": "apply_staged" })).0, 200);
+    let soon = chrono::Utc::now() + chrono::Duration::seconds(2);
+    assert_eq!(gui.post("/api/instance/mock/schedule", json!({ "at": soon, "action": "restart" })).0, 200);
+
+    // The restart is due while the GUI is stopped
+    let _ = gui.child.kill();
+    let _ = gui.child.wait();
+    thread::sleep(Duration::from_secs(3));
+    gui.restart();
+
+    let s = gui.snapshot("mock");
+    assert_eq!(s["schedule"].as_array().unwrap().len(), 1, "{}", s["schedule"]);
+    assert_eq!(s["schedule"][0]["action"], "apply_staged");
+    assert!(s["staged"]["changes"][0].as_str().unwrap().contains("bitrate"));
+    // Not carried out late
+    assert_eq!(s["history"][0]["action"], "restart");
+    assert_eq!(s["history"][0]["result"], "failed");
+    assert!(s["history"][0]["error"].as_str().unwrap().contains("not running"), "{}", s["history"]);
+    assert_eq!(s["process"]["state"], "stopped");
+    assert!(!gui.dir.path.join("mock.json").exists());
+
+    // Stays staged until it is no longer scheduled
+    assert_eq!(gui.request("DELETE", "/api/instance/mock/stage", None).0, 409);
+    let id = s["schedule"][0]["id"].as_u64().unwrap();
+    assert_eq!(gui.request("DELETE", &format!("/api/instance/mock/schedule/{}", id), None).0, 200);
+    assert_eq!(gui.request("DELETE", "/api/instance/mock/stage", None).0, 200);
+    assert!(gui.snapshot("mock")["staged"].is_null());
+}
@@ -0,0 +1,233 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The schedule that plans restarts, and checks the mux after a change of configuration
+
+mod common;
+
+use chrono::{Duration, Utc};
+use serde_json::Value;
+
+use odr_dabmux_gui::{
+    config::{Config, GuiConfig},
+    muxlog::MuxLog,
+    poller::Poller,
+    scheduler::{Action, ActionResult, Health, Schedule, Step, Target},
+    supervisor::{ProcessState, Supervisor},
+    DabMuxHandle,
+};
+use common::{test_config, MockMux, MockState, TestDir};
+
+#[test]
+fn schedule() {
+    let now = Utc::now();
+    let mut schedule = Schedule::default();
+    let later = schedule.add(now + Duration::hours(2), Action::Restart);
+    let soon = schedule.add(now + Duration::hours(1), Action::Restart);
+    let first : Vec<u64> = schedule.upcoming().iter().map(|a| a.id).collect();
+    assert_eq!(first, [soon, later]);
+
+    assert!(schedule.take_due(now).is_empty());
+    let due = schedule.take_due(now + Duration::minutes(90));
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].id, soon);
+
+    assert!(schedule.cancel(later));
+    assert!(!schedule.cancel(later));
+    assert!(schedule.upcoming().is_empty());
+}
+
+#[test]
+fn staged_verification() {
+    let now = Utc::now();
+    let previous = test_config("previous");
+    let mut staged = previous.clone();
+    staged.services[0].bitrate = 96;
+
+    let mut schedule = Schedule::default();
+    schedule.stage(staged);
+    let action = Action::ApplyStaged { health_timeout_s: 30 };
+    schedule.add(now, action.clone());
+    let due = schedule.take_due(now);
+    assert_eq!(due[0].action, action);
+    assert_eq!(schedule.take_staged().unwrap().services[0].bitrate, 96);
+    assert!(schedule.staged().is_none());
+
+    // Healthy in time
+    schedule.verify(&due[0], previous.clone(), now, Duration::seconds(30));
+    assert!(schedule.check(Health::Starting, now + Duration::seconds(10)).is_none());
+    assert!(schedule.verifying().is_some());
+    assert!(schedule.check(Health::Healthy, now + Duration::seconds(20)).is_none());
+    assert!(schedule.verifying().is_none());
+    assert_eq!(schedule.history()[0].result, ActionResult::Done);
+
+    // Exited
+    schedule.verify(&due[0], previous.clone(), now, Duration::seconds(30));
+    let restored = schedule.check(Health::Failed("exit code 1".to_owned()), now + Duration::seconds(1));
+    assert_eq!(restored.unwrap().services[0].bitrate, previous.services[0].bitrate);
+    assert_eq!(schedule.history()[0].result, ActionResult::RolledBack { reason: "exit code 1".to_owned() });
+
+    // Never answered
+    schedule.verify(&due[0], previous.clone(), now, Duration::seconds(30));
+    assert!(schedule.check(Health::Starting, now + Duration::seconds(30)).is_some());
+    assert!(matches!(schedule.history()[0].result, ActionResult::RolledBack { .. }));
+    assert_eq!(schedule.history().len(), 3);
+}
+
+#[test]
+fn stored_schedule() {
+    let now = Utc::now();
+    let previous = test_config("stored");
+    let mut schedule = Schedule::default();
+    let missed = schedule.add(now - Duration::minutes(5), Action::Restart);
+    let later = schedule.add(now + Duration::hours(1), Action::ApplyStaged { health_timeout_s: 30 });
+    let mut staged = previous.clone();
+    staged.services[0].bitrate = 96;
+    schedule.stage(staged);
+    let verified = schedule.add(now, Action::ApplyStaged { health_timeout_s: 30 });
+    let due = schedule.take_due(now);
+    assert_eq!(due.iter().map(|a| a.id).collect::<Vec<_>>(), [missed, verified]);
+    schedule.verify(&due[1], previous.clone(), now, Duration::seconds(30));
+    schedule.add(now - Duration::minutes(5), Action::Restart);
+
+    let mut gui_conf = GuiConfig { instances: vec![previous.clone()], templates: Default::default(), schedules: Default::default() };
+    gui_conf.schedules.insert("stored".to_owned(), schedule);
+    let gui_conf : GuiConfig = toml::from_str(&toml::to_string_pretty(&gui_conf).unwrap()).unwrap();
+    let mut schedule = gui_conf.schedules["stored"].clone();
+
+    // Overdue when loaded
+    let missed = schedule.missed(now);
+    assert_eq!(missed.len(), 1);
+    assert!(matches!(&schedule.history()[0].result, ActionResult::Failed { error } if error.contains("not running")));
+    assert_eq!(schedule.upcoming().iter().map(|a| a.id).collect::<Vec<_>>(), [later]);
+    assert_eq!(schedule.staged().unwrap().conf.services[0].bitrate, 96);
+
+    // Still rolled back after the restart of the GUI
+    assert_eq!(schedule.verifying().unwrap().id, verified);
+    let restored = schedule.check(Health::Starting, now + Duration::seconds(30)).unwrap();
+    assert_eq!(restored.services[0].bitrate, previous.services[0].bitrate);
+}
+
+/// A mock mux, and an odr-dabmux that runs a shell script
+struct ShellMux {
+    mock : MockMux,
+    dir : TestDir,
+    log : MuxLog,
+    supervisor : Supervisor,
+    poller : Poller,
+}
+
+impl ShellMux {
+    /// Start running `exec sleep 30` with its configuration
+    fn start(test: &str) -> (Self, Config) {
+        let mock = MockMux::start(MockState::default());
+        let dir = TestDir::new(test);
+        let log = MuxLog::default();
+        let conf = Self::config(&mock, &dir, "exec sleep 30");
+        let supervisor = Supervisor::spawn("mock", conf.supervisor_settings(), log.clone());
+        let poller = Poller::spawn(DabMuxHandle::spawn("mock", mock.dabmux()), conf.poll_interval(), dir.path.join("mock.json"));
+        conf.write_dabmux_json().unwrap();
+        supervisor.start();
+        (Self { mock, dir, log, supervisor, poller }, conf)
+    }
+
+    fn config(mock: &MockMux, dir: &TestDir, script: &str) -> Config {
+        let mut conf = mock.config("mock");
+        conf.dabmux_config_location = dir.path.join("mock.json").display().to_string();
+        conf.process.binary = "sh".to_owned();
+        conf.process.args = vec!["-c".to_owned(), script.to_owned()];
+        conf.process.auto_restart = false;
+        conf
+    }
+
+    fn target(&self) -> Target<'_> {
+        Target { name: "mock", supervisor: &self.supervisor, poller: &self.poller, log: &self.log }
+    }
+
+    fn written_bitrate(&self) -> Value {
+        let json : Value = serde_json::from_str(&std::fs::read_to_string(self.dir.path.join("mock.json")).unwrap()).unwrap();
+        json["subchannels"]["sub-station2"]["bitrate"].clone()
+    }
+}
+
+/// Run the schedule until it decides something
+async fn run_until(schedule: &mut Schedule, target: &Target<'_>, conf: &Config) -> Step {
+    let deadline = Utc::now() + Duration::seconds(10);
+    loop {
+        let step = schedule.run(target, conf, &[], Utc::now());
+        if step.changed {
+            return step;
+        }
+        assert!(Utc::now() < deadline, "timeout waiting for the schedule");
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+}
+
+/// Switch to a staged configuration whose odr-dabmux runs `script`, and return the schedule
+/// with the outcome of the verification
+async fn apply_staged(mux: &ShellMux, previous: &Config, script: &str) -> (Schedule, Step) {
+    let mut staged = ShellMux::config(&mux.mock, &mux.dir, script);
+    staged.services[1].bitrate = 96;
+    let mut schedule = Schedule::default();
+    schedule.stage(staged.clone());
+    schedule.add(Utc::now(), Action::ApplyStaged { health_timeout_s: 5 });
+
+    let mut step = run_until(&mut schedule, &mux.target(), previous).await;
+    assert_eq!(step.restarts.len(), 1);
+    let restart = step.restarts.remove(0);
+    assert!(restart.switches());
+    assert_eq!(restart.conf.services[1].bitrate, 96);
+    let result = restart.run(&mux.supervisor);
+    assert!(schedule.restarted(&mux.target(), restart, result).is_none());
+    assert!(schedule.verifying().is_some());
+    assert_eq!(mux.written_bitrate(), 96);
+
+    let step = run_until(&mut schedule, &mux.target(), &staged).await;
+    (schedule, step)
+}
+
+#[tokio::test]
+async fn staged_applied() {
+    let (mux, previous) = ShellMux::start("scheduler_applied");
+    let (schedule, step) = apply_staged(&mux, &previous, "exec sleep 30").await;
+    assert!(step.restarts.is_empty());
+    assert!(schedule.verifying().is_none());
+    assert_eq!(schedule.history()[0].result, ActionResult::Done);
+    assert!(matches!(mux.supervisor.status().state, ProcessState::Running { .. }));
+}
+
+#[tokio::test]
+async fn staged_rolled_back() {
+    let (mux, previous) = ShellMux::start("scheduler_rolled_back");
+    let (mut schedule, mut step) = apply_staged(&mux, &previous, "exit 1").await;
+    assert!(matches!(&schedule.history()[0].result, ActionResult::RolledBack { reason } if reason.contains("exited")),
+        "{:?}", schedule.history());
+    assert!(mux.log.lines().iter().any(|l| l.text.contains("Rolled back")));
+
+    // Back to the previous configuration, written and running again
+    assert_eq!(step.restarts.len(), 1);
+    let rollback = step.restarts.remove(0);
+    assert!(rollback.switches());
+    assert_eq!(rollback.conf.process.args, previous.process.args);
+    let result = rollback.run(&mux.supervisor);
+    assert!(schedule.restarted(&mux.target(), rollback, result).is_none());
+    assert_ne!(mux.written_bitrate(), 96);
+
+    let deadline = Utc::now() + Duration::seconds(10);
+    while !matches!(mux.supervisor.status().state, ProcessState::Running { .. }) {
+        assert!(Utc::now() < deadline, "timeout waiting for odr-dabmux to run");
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert!(mux.supervisor.status().command_line.contains("sleep 30"));
+}
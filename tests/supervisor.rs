@@ -0,0 +1,167 @@
+/*
+ * A Configuration and Control UI for ODR-DabMux
+ * Copyright (C) 2024 Matthias P. Braendli
+ *
+ * This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public
+ * License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied
+ * warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The process supervisor, with shell commands standing in for odr-dabmux, and the tail of its log file
+
+#![cfg(unix)]
+
+mod common;
+
+use std::{io::Write, time::Duration};
+
+use odr_dabmux_gui::{
+    muxlog::{FileTail, LogLevel, LogSource, MuxLog},
+    supervisor::{ProcessState, ProcessStatus, Supervisor, SupervisorSettings},
+};
+use common::TestDir;
+
+fn shell(script: &str, auto_restart: bool) -> SupervisorSettings {
+    SupervisorSettings {
+        program: "sh".to_owned(),
+        args: vec!["-c".to_owned(), script.to_owned()],
+        auto_restart,
+    }
+}
+
+async fn wait_for(supervisor: &Supervisor, mut condition: impl FnMut(&ProcessStatus) -> bool) -> ProcessStatus {
+    let mut rx = supervisor.subscribe();
+    let status = tokio::time::timeout(Duration::from_secs(10), rx.wait_for(|s| condition(s)))
+        .await
+        .expect("timeout waiting for the process status")
+        .unwrap()
+        .clone();
+    status
+}
+
+#[tokio::test]
+async fn restart_after_crash() {
+    let supervisor = Supervisor::spawn("test", shell("exit 3", true), MuxLog::default());
+    supervisor.start();
+
+    let status = wait_for(&supervisor, |s| s.exits.len() >= 2).await;
+    assert!(status.exits.iter().all(|e| e.code == Some(3) && !e.requested));
+    assert!(status.restarts >= 2);
+    assert!(matches!(status.state, ProcessState::Restarting { .. } | ProcessState::Running { .. }));
+
+    supervisor.stop();
+    wait_for(&supervisor, |s| s.state == ProcessState::Stopped).await;
+}
+
+#[tokio::test]
+async fn exit_without_restart() {
+    let supervisor = Supervisor::spawn("test", shell("exit 0", false), MuxLog::default());
+    supervisor.start();
+
+    let status = wait_for(&supervisor, |s| !s.exits.is_empty()).await;
+    assert_eq!(status.exits[0].code, Some(0));
+    assert_eq!(status.state, ProcessState::Stopped);
+    assert_eq!(status.restarts, 0);
+}
+
+#[tokio::test]
+async fn stop_and_restart() {
+    let supervisor = Supervisor::spawn("test", shell("exec sleep 30", true), MuxLog::default());
+    supervisor.start();
+    let status = wait_for(&supervisor, |s| matches!(s.state, ProcessState::Running { .. })).await;
+    let ProcessState::Running { pid: first_pid, .. } = status.state else { unreachable!() };
+
+    supervisor.restart();
+    let status = wait_for(&supervisor, |s| matches!(s.state, ProcessState::Running { pid, .. } if pid != first_pid)).await;
+    assert_eq!(status.exits[0].signal, Some(libc::SIGTERM));
+    assert!(status.exits[0].requested);
+
+    supervisor.stop();
+    let status = wait_for(&supervisor, |s| s.state == ProcessState::Stopped).await;
+    assert_eq!(status.exits.len(), 2);
+    assert_eq!(status.restarts, 0);
+}
+
+#[tokio::test]
+async fn reconfigure() {
+    let supervisor = Supervisor::spawn("test", shell("exec sleep 30", true), MuxLog::default());
+    supervisor.reconfigure(shell("exit 7", false));
+    let status = wait_for(&supervisor, |s| s.command_line.contains("exit 7")).await;
+    assert_eq!(status.state, ProcessState::Stopped);
+
+    supervisor.start();
+    let status = wait_for(&supervisor, |s| !s.exits.is_empty()).await;
+    assert_eq!(status.exits[0].code, Some(7));
+}
+
+#[tokio::test]
+async fn missing_executable() {
+    let supervisor = Supervisor::spawn("test", SupervisorSettings {
+        program: "/nonexistent/odr-dabmux".to_owned(),
+        args: vec![],
+        auto_restart: true,
+    }, MuxLog::default());
+    supervisor.start();
+
+    let status = wait_for(&supervisor, |s| matches!(s.state, ProcessState::Failed { .. })).await;
+    assert!(status.exits.is_empty());
+}
+
+#[tokio::test]
+async fn output_captured() {
+    let log = MuxLog::default();
+    let (_, mut rx) = log.subscribe();
+    let supervisor = Supervisor::spawn("test", shell("echo 'INFO: starting'; echo 'ERROR: no input' >&2; exit 1", false), log.clone());
+    supervisor.start();
+    wait_for(&supervisor, |s| !s.exits.is_empty()).await;
+
+    let mut lines = Vec::new();
+    while lines.len() < 4 {
+        let line = tokio::time::timeout(Duration::from_secs(10), rx.recv()).await.unwrap().unwrap();
+        lines.push(line);
+    }
+    let find = |text: &str| lines.iter().find(|l| l.text == text).unwrap_or_else(|| panic!("{} missing", text));
+    assert_eq!(find("INFO: starting").source, LogSource::Stdout);
+    assert_eq!(find("ERROR: no input").level, LogLevel::Error);
+    assert!(lines.iter().any(|l| l.source == LogSource::Supervisor && l.text.starts_with("Process exited with exit code 1")));
+}
+
+#[tokio::test]
+async fn file_tail_burst() {
+    let dir = TestDir::new("file_tail_burst");
+    let path = dir.path.join("mux.log");
+    std::fs::write(&path, "first line\n").unwrap();
+    let log = MuxLog::default();
+    let _tail = FileTail::spawn(path.clone(), log.clone());
+
+    let wait_for_line = |text: &'static str| {
+        let log = log.clone();
+        async move {
+            tokio::time::timeout(Duration::from_secs(10), async {
+                while !log.lines().iter().any(|l| l.text == text) {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            }).await.unwrap_or_else(|_| panic!("{} missing", text));
+        }
+    };
+    wait_for_line("first line").await;
+
+    // Far more than is read at once, in fewer lines than the log keeps
+    let mut burst : String = (0..10_000).map(|i| format!("burst line {:090}\n", i)).collect();
+    burst.push_str("last line\n");
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(burst.as_bytes()).unwrap();
+    drop(file);
+    wait_for_line("last line").await;
+
+    let lines = log.lines();
+    assert!(lines.iter().any(|l| l.source == LogSource::Supervisor && l.text.starts_with("Skipped")));
+    assert!(!lines.iter().any(|l| l.text == format!("burst line {:090}", 0)));
+    // Only complete lines
+    assert!(lines.iter().filter(|l| l.source == LogSource::File).all(|l| l.text.ends_with("line") || l.text.starts_with("burst line ")));
+}